@@ -0,0 +1,66 @@
+package main
+
+import (
+	"bufio"
+	"fmt"
+	"io"
+	"strings"
+
+	"filippo.io/age"
+	"filippo.io/age/agessh"
+	"filippo.io/age/armor"
+)
+
+// parseRecipient parses a ?to= recipient, an age1... key or an ssh-ed25519
+// or ssh-rsa public key as found in authorized_keys.
+func parseRecipient(s string) (age.Recipient, error) {
+	s = strings.TrimSpace(s)
+	switch {
+	case strings.HasPrefix(s, "age1"):
+		r, err := age.ParseX25519Recipient(s)
+		if err != nil {
+			return nil, badUpload(fmt.Sprintf("bad age recipient %q: %s", s, err.Error()))
+		}
+		return r, nil
+	case strings.HasPrefix(s, "ssh-"):
+		r, err := agessh.ParseRecipient(s)
+		if err != nil {
+			return nil, badUpload(fmt.Sprintf("bad ssh recipient %q: %s", s, err.Error()))
+		}
+		return r, nil
+	}
+	return nil, badUpload(fmt.Sprintf("unknown recipient %q, use an age1... or ssh public key", s))
+}
+
+// ageSealer is the sealer for ?to= uploads. The paste is encrypted to the
+// recipients as armored text, so it's still a text paste and only they can
+// decrypt it, with `age -d`. age works on writers, so it's run in a pipe.
+func ageSealer(recipients []age.Recipient) sealer {
+	return func(r io.Reader) io.Reader {
+		// turn a tiny paste away before anything is encrypted
+		br := bufio.NewReader(r)
+		if _, err := br.Peek(minPasteSize); err == io.EOF {
+			return errReader{pasteTooSmall{}}
+		} else if err != nil {
+			return errReader{err}
+		}
+		return pipeSeal(br, func(w io.Writer, r io.Reader) error {
+			return ageEncrypt(w, r, recipients)
+		})
+	}
+}
+
+func ageEncrypt(dst io.Writer, r io.Reader, recipients []age.Recipient) error {
+	a := armor.NewWriter(dst)
+	w, err := age.Encrypt(a, recipients...)
+	if err != nil {
+		return err
+	}
+	if _, err := io.Copy(w, r); err != nil {
+		return err
+	}
+	if err := w.Close(); err != nil {
+		return err
+	}
+	return a.Close()
+}
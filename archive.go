@@ -0,0 +1,396 @@
+package main
+
+import (
+	"archive/tar"
+	"archive/zip"
+	"bufio"
+	"bytes"
+	"compress/gzip"
+	"fmt"
+	"io"
+	"io/ioutil"
+	"log"
+	"mime"
+	"net/http"
+	"os"
+	"path"
+	"strconv"
+	"strings"
+	"time"
+)
+
+// archiveFormats are the ?<format> query modes a directory can be downloaded
+// as, and their content types.
+var archiveFormats = map[string]string{
+	"tar":    "application/x-tar",
+	"tar.gz": "application/gzip",
+	"zip":    "application/zip",
+}
+
+// archiveFormat returns the archive format asked for in the query, if any.
+func archiveFormat(req *http.Request) string {
+	query := req.URL.Query()
+	for format := range archiveFormats {
+		if _, ok := query[format]; ok {
+			return format
+		}
+	}
+	return ""
+}
+
+// walkDir calls fn for every entry below the directory at key, parents
+// before their children, with its slash separated path relative to key.
+func walkDir(s store, key, prefix string, fn func(name string, e entry) error) error {
+	entries, err := s.Ls(key)
+	if err != nil {
+		return err
+	}
+	for _, e := range entries {
+		name := path.Join(prefix, e.Name)
+		if err := fn(name, e); err != nil {
+			return err
+		}
+		if e.IsDir {
+			if err := walkDir(s, key+"/"+e.Name, name, fn); err != nil {
+				return err
+			}
+		}
+	}
+	return nil
+}
+
+// copyFile streams the file at key into w.
+func copyFile(s store, key string, w io.Writer) error {
+	r, err := s.Cat(key, 0, -1)
+	if err != nil {
+		return err
+	}
+	defer r.Close()
+	_, err = io.Copy(w, r)
+	return err
+}
+
+// archive streams the directory at key as a tar, tar.gz or zip. Files are
+// read from the store one at a time as they're written, nothing is buffered.
+func (h *handler) archive(w http.ResponseWriter, req *http.Request, key, format string, modTime time.Time) {
+	key = strings.TrimSuffix(key, "/")
+	root := path.Base(key)
+	w.Header().Set("Content-Type", archiveFormats[format])
+	w.Header().Set("Content-Disposition", mime.FormatMediaType("attachment", map[string]string{"filename": root + "." + format}))
+	if req.Method == "HEAD" {
+		return
+	}
+
+	var err error
+	switch format {
+	case "tar":
+		err = writeTar(h.store, key, root, modTime, w)
+	case "tar.gz":
+		gz := gzip.NewWriter(w)
+		err = writeTar(h.store, key, root, modTime, gz)
+		if cerr := gz.Close(); err == nil {
+			err = cerr
+		}
+	case "zip":
+		err = writeZip(h.store, key, root, modTime, w)
+	}
+	if err != nil {
+		// the headers are gone already, all we can do is cut it short
+		log.Printf("[ERROR] %s (%s: %s)\n", key, format, err.Error())
+	}
+}
+
+func writeTar(s store, key, root string, modTime time.Time, w io.Writer) error {
+	tw := tar.NewWriter(w)
+	err := tw.WriteHeader(&tar.Header{Name: root + "/", Mode: 0755, ModTime: modTime, Typeflag: tar.TypeDir})
+	if err != nil {
+		return err
+	}
+	err = walkDir(s, key, root, func(name string, e entry) error {
+		if e.IsDir {
+			return tw.WriteHeader(&tar.Header{Name: name + "/", Mode: 0755, ModTime: modTime, Typeflag: tar.TypeDir})
+		}
+		err := tw.WriteHeader(&tar.Header{Name: name, Mode: 0644, Size: e.Size, ModTime: modTime, Typeflag: tar.TypeReg})
+		if err != nil {
+			return err
+		}
+		return copyFile(s, key+strings.TrimPrefix(name, root), tw)
+	})
+	if err != nil {
+		return err
+	}
+	return tw.Close()
+}
+
+func writeZip(s store, key, root string, modTime time.Time, w io.Writer) error {
+	zw := zip.NewWriter(w)
+	err := walkDir(s, key, root, func(name string, e entry) error {
+		if e.IsDir {
+			_, err := zw.CreateHeader(&zip.FileHeader{Name: name + "/", Modified: modTime})
+			return err
+		}
+		fw, err := zw.CreateHeader(&zip.FileHeader{Name: name, Method: zip.Deflate, Modified: modTime})
+		if err != nil {
+			return err
+		}
+		return copyFile(s, key+strings.TrimPrefix(name, root), fw)
+	})
+	if err != nil {
+		return err
+	}
+	return zw.Close()
+}
+
+// extractReader counts what comes out of an archive and stops it expanding
+// past maxPasteSize, so a small upload can't fill the disk.
+type extractReader struct {
+	r io.Reader
+	n int64
+}
+
+func (e *extractReader) Read(b []byte) (int, error) {
+	n, err := e.r.Read(b)
+	e.n += int64(n)
+	if e.n > maxPasteSize {
+		return n, badUpload(fmt.Sprintf("archive expands past %d bytes", maxPasteSize))
+	}
+	return n, err
+}
+
+// extract unpacks a tar, tar.gz or zip archive into dir, telling them apart
+// by their magic bytes. It returns how many bytes the archive expanded to.
+func extract(dir string, r io.Reader) (int64, error) {
+	br := bufio.NewReader(r)
+	magic, _ := br.Peek(4)
+	switch {
+	case bytes.HasPrefix(magic, []byte("\x1f\x8b")):
+		gz, err := gzip.NewReader(br)
+		if err != nil {
+			return 0, badUpload("bad gzip archive: " + err.Error())
+		}
+		defer gz.Close()
+		r = gz
+	case bytes.HasPrefix(magic, []byte("PK\x03\x04")):
+		return unzip(dir, br)
+	default:
+		r = br
+	}
+	// untar counts what comes out, sparse members expand without reading
+	return untar(dir, r)
+}
+
+// unzip extracts a zip archive into dir with the same rules as untar. Zip
+// needs random access, so the upload is staged next to dir first.
+func unzip(dir string, r io.Reader) (int64, error) {
+	staged := dir + ".zip"
+	defer os.Remove(staged)
+	if err := saveFile(path.Dir(staged), path.Base(staged), r); err != nil {
+		return 0, err
+	}
+	zr, err := zip.OpenReader(staged)
+	if err != nil {
+		return 0, badUpload("bad zip archive: " + err.Error())
+	}
+	defer zr.Close()
+	if len(zr.File) > maxArchiveFiles {
+		return 0, badUpload(fmt.Sprintf("archive has more than %d files", maxArchiveFiles))
+	}
+
+	er := &extractReader{}
+	for _, f := range zr.File {
+		name, ok := archivePath(f.Name)
+		if !ok {
+			return er.n, badUpload(fmt.Sprintf("unsafe path in archive %q", f.Name))
+		} else if name == "" {
+			continue
+		}
+
+		mode := f.Mode()
+		switch {
+		case mode.IsDir():
+			err = os.MkdirAll(path.Join(dir, name), 0755)
+		case mode.IsRegular():
+			if f.UncompressedSize64 > maxPasteSize {
+				return er.n, badUpload(fmt.Sprintf("archive expands past %d bytes", maxPasteSize))
+			}
+			if err = os.MkdirAll(path.Join(dir, path.Dir(name)), 0755); err != nil {
+				break
+			}
+			var fr io.ReadCloser
+			if fr, err = f.Open(); err != nil {
+				return er.n, badUpload("bad zip archive: " + err.Error())
+			}
+			// the declared sizes can lie, count what actually comes out
+			er.r = fr
+			err = saveFile(dir, name, er)
+			fr.Close()
+		}
+		if err != nil {
+			return er.n, err
+		}
+	}
+	return er.n, nil
+}
+
+// archiveKind returns the format of an archive by its file name, tar, tar.gz
+// or zip, or nothing for other files.
+func archiveKind(name string) string {
+	name = strings.ToLower(name)
+	switch {
+	case strings.HasSuffix(name, ".tar"):
+		return "tar"
+	case strings.HasSuffix(name, ".tar.gz"), strings.HasSuffix(name, ".tgz"):
+		return "tar.gz"
+	case strings.HasSuffix(name, ".zip"):
+		return "zip"
+	}
+	return ""
+}
+
+// splitArchivePath splits a key like "<hash>/logs.tar.gz/var/log/syslog" at
+// the first archive in it, into the archive and the member path inside it.
+func splitArchivePath(key string) (archive, member string, ok bool) {
+	parts := strings.Split(strings.Trim(key, "/"), "/")
+	for i := 1; i < len(parts)-1; i++ {
+		if archiveKind(parts[i]) != "" {
+			return strings.Join(parts[:i+1], "/"), strings.Join(parts[i+1:], "/"), true
+		}
+	}
+	return "", "", false
+}
+
+// archiveMember is a file or directory inside a stored archive. open is only
+// valid during the walkArchive callback that got it.
+type archiveMember struct {
+	entry
+	open func() (io.ReadCloser, error)
+}
+
+// walkArchive calls fn for each member of the archive at key, in archive
+// order, until fn returns false. Tars are streamed from the start, zips are
+// read in place from their central directory.
+func walkArchive(s store, key string, size int64, fn func(m archiveMember) (bool, error)) error {
+	f := &pasteFile{s: s, key: key, size: size}
+	defer f.Close()
+
+	kind := archiveKind(key)
+	if kind == "zip" {
+		zr, err := zip.NewReader(f, size)
+		if err != nil {
+			return badUpload("bad zip archive: " + err.Error())
+		}
+		for _, zf := range zr.File {
+			name, ok := archivePath(zf.Name)
+			mode := zf.Mode()
+			if !ok || name == "" || !(mode.IsDir() || mode.IsRegular()) {
+				continue
+			}
+			m := archiveMember{entry{Name: name, Size: int64(zf.UncompressedSize64), IsDir: mode.IsDir()}, zf.Open}
+			if more, err := fn(m); !more || err != nil {
+				return err
+			}
+		}
+		return nil
+	}
+
+	var r io.Reader = f
+	if kind == "tar.gz" {
+		gz, err := gzip.NewReader(f)
+		if err != nil {
+			return badUpload("bad gzip archive: " + err.Error())
+		}
+		defer gz.Close()
+		r = gz
+	}
+	tr := tar.NewReader(r)
+	for {
+		hdr, err := tr.Next()
+		if err == io.EOF {
+			return nil
+		} else if err != nil {
+			return badUpload("bad tar archive: " + err.Error())
+		}
+		name, ok := archivePath(hdr.Name)
+		mode := hdr.FileInfo().Mode()
+		if !ok || name == "" || !(mode.IsDir() || mode.IsRegular()) {
+			continue
+		}
+		m := archiveMember{entry{Name: name, Size: hdr.Size, IsDir: mode.IsDir()},
+			func() (io.ReadCloser, error) { return ioutil.NopCloser(tr), nil }}
+		if more, err := fn(m); !more || err != nil {
+			return err
+		}
+	}
+}
+
+// browse lists the members of the archive at key with ?ls, or serves the
+// single member, raw or rendered like any other paste.
+func (h *handler) browse(w http.ResponseWriter, req *http.Request, key, member string, info entry) {
+	if member == "" {
+		var entries []entry
+		err := walkArchive(h.store, key, info.Size, func(m archiveMember) (bool, error) {
+			if m.IsDir {
+				m.Size = 0
+			}
+			entries = append(entries, m.entry)
+			return true, nil
+		})
+		if err != nil {
+			http.Error(w, err.Error(), http.StatusUnprocessableEntity)
+			log.Printf("[ERROR] %s (ls: %s)\n", key, err.Error())
+			return
+		}
+		writeListing(w, req, key, info, entries)
+		return
+	}
+
+	found := false
+	err := walkArchive(h.store, key, info.Size, func(m archiveMember) (bool, error) {
+		if m.Name != member || m.IsDir {
+			return true, nil
+		}
+		found = true
+		r, err := m.open()
+		if err != nil {
+			return false, err
+		}
+		defer r.Close()
+		if req.URL.RawQuery != "" {
+			paste, err := ioutil.ReadAll(io.LimitReader(r, maxPasteSize+1))
+			if err != nil {
+				return false, err
+			} else if len(paste) > maxPasteSize {
+				return false, pasteTooLarge{}
+			}
+			render(w, req, key+"/"+member, paste)
+			return false, nil
+		}
+
+		br := bufio.NewReader(r)
+		setContentType(w, path.Base(member), func() []byte {
+			head, _ := br.Peek(512)
+			return head
+		})
+		w.Header().Set("Content-Length", strconv.FormatInt(m.Size, 10))
+		if req.Method != "HEAD" {
+			io.Copy(w, br)
+		}
+		return false, nil
+	})
+	switch {
+	case err != nil && !found:
+		http.Error(w, err.Error(), http.StatusUnprocessableEntity)
+	case err != nil:
+		if _, ok := err.(pasteTooLarge); ok {
+			http.Error(w, err.Error(), http.StatusRequestEntityTooLarge)
+		} else {
+			http.Error(w, err.Error(), http.StatusInternalServerError)
+		}
+	case !found:
+		http.Error(w, "not found", http.StatusNotFound)
+		err = pasteNotFound{}
+	}
+	if err != nil {
+		log.Printf("[ERROR] %s/%s (%s)\n", key, member, err.Error())
+	}
+}
@@ -0,0 +1,209 @@
+package main
+
+import (
+	"container/list"
+	"encoding/json"
+	"expvar"
+	"io"
+	"io/ioutil"
+	"log"
+	"os"
+	"path"
+	"strings"
+	"sync"
+
+	"github.com/peterbourgon/diskv"
+)
+
+// read cache counters, published on /debug/vars
+var (
+	cacheHits   = expvar.NewInt("cache_hits")
+	cacheMisses = expvar.NewInt("cache_misses")
+)
+
+// cachedStore is a read-through cache in front of another store. Files are
+// kept in diskv keyed by their cid, with diskv's own memory cache in front of
+// the disk, and the disk side is bounded by evicting the least recently used
+// entries. Keys are content addressed so a cached copy never goes stale, but
+// it's dropped when a root it was read under is unpinned.
+type cachedStore struct {
+	store
+	d   *diskv.Diskv
+	tmp string // files being fetched, moved into d once they're whole
+
+	mu    sync.Mutex
+	lru   *list.List               // most recently used at the front
+	items map[string]*list.Element // diskv key -> lru element
+	size  int64
+	max   int64
+}
+
+type cacheItem struct {
+	key   string
+	size  int64
+	roots map[string]bool // roots it was read under
+}
+
+type readCloser struct {
+	io.Reader
+	io.Closer
+}
+
+func newCachedStore(s store, dir string, max int64) *cachedStore {
+	c := &cachedStore{
+		store: s,
+		d: diskv.New(diskv.Options{
+			BasePath:     dir,
+			CacheSizeMax: memCacheSize,
+		}),
+		tmp:   path.Join(dir, ".tmp"),
+		lru:   list.New(),
+		items: make(map[string]*list.Element),
+		max:   max,
+	}
+	c.d.EraseAll() // sizes aren't tracked across restarts, start clean
+	os.RemoveAll(c.tmp)
+	if err := os.MkdirAll(c.tmp, 0755); err != nil {
+		log.Printf("[ERROR] %s (cache: %s)\n", c.tmp, err.Error())
+	}
+	return c
+}
+
+// keyRoot is the paste a store key is in.
+func keyRoot(key string) string {
+	return strings.SplitN(key, "/", 2)[0]
+}
+
+// touch marks key, read under root, as recently used, evicting old entries
+// to stay under max.
+func (c *cachedStore) touch(root, key string, size int64) {
+	var evicted []string
+	c.mu.Lock()
+	if el, ok := c.items[key]; ok {
+		el.Value.(*cacheItem).roots[root] = true
+		c.lru.MoveToFront(el)
+	} else {
+		c.items[key] = c.lru.PushFront(&cacheItem{key, size, map[string]bool{root: true}})
+		c.size += size
+		for c.size > c.max && c.lru.Len() > 1 {
+			evicted = append(evicted, c.evict(c.lru.Back()))
+		}
+	}
+	c.mu.Unlock()
+	c.erase(evicted)
+}
+
+// evict drops a cached entry from the lru and returns its key, for erase once
+// the lock is released. Callers hold the lock.
+func (c *cachedStore) evict(el *list.Element) string {
+	it := el.Value.(*cacheItem)
+	c.lru.Remove(el)
+	delete(c.items, it.key)
+	c.size -= it.size
+	return it.key
+}
+
+// erase removes evicted entries from diskv. It's never called with mu held,
+// so reads aren't held up by diskv's own lock.
+func (c *cachedStore) erase(keys []string) {
+	for _, key := range keys {
+		c.d.Erase(key)
+	}
+}
+
+// Unpin drops everything cached from root before unpinning it, so a burned
+// or deleted paste doesn't live on in the cache.
+func (c *cachedStore) Unpin(root string) error {
+	var evicted []string
+	c.mu.Lock()
+	for el := c.lru.Front(); el != nil; {
+		next := el.Next()
+		if el.Value.(*cacheItem).roots[root] {
+			evicted = append(evicted, c.evict(el))
+		}
+		el = next
+	}
+	c.mu.Unlock()
+	c.erase(evicted)
+	return c.store.Unpin(root)
+}
+
+// fetch copies key from the backend into the cache as hash. The copy goes to
+// a temp file first, diskv locks everything while it writes and the backend
+// may be slow, and is then moved in.
+func (c *cachedStore) fetch(key, hash string) error {
+	r, err := c.store.Cat(key, 0, -1)
+	if err != nil {
+		return err
+	}
+	defer r.Close()
+	f, err := os.CreateTemp(c.tmp, "fetch-")
+	if err != nil {
+		return err
+	}
+	defer os.Remove(f.Name())
+	_, err = io.Copy(f, r)
+	if cerr := f.Close(); err == nil {
+		err = cerr
+	}
+	if err != nil {
+		return err
+	}
+	return c.d.Import(f.Name(), hash, true)
+}
+
+func (c *cachedStore) Stat(key string) (entry, error) {
+	skey := "stat-" + sha256Hex([]byte(key))
+	if b, err := c.d.Read(skey); err == nil {
+		var e entry
+		if json.Unmarshal(b, &e) == nil {
+			c.touch(keyRoot(key), skey, int64(len(b)))
+			return e, nil
+		}
+	}
+
+	e, err := c.store.Stat(key)
+	if err != nil {
+		return e, err
+	}
+	if b, err := json.Marshal(e); err == nil && c.d.Write(skey, b) == nil {
+		c.touch(keyRoot(key), skey, int64(len(b)))
+	}
+	return e, nil
+}
+
+func (c *cachedStore) Cat(key string, offset, length int64) (io.ReadCloser, error) {
+	e, err := c.Stat(key)
+	if err != nil {
+		return nil, err
+	}
+	// don't let a single large file flush everything else
+	if e.IsDir || e.Size > c.max/8 {
+		return c.store.Cat(key, offset, length)
+	}
+
+	if c.d.Has(e.Hash) {
+		cacheHits.Add(1)
+	} else {
+		cacheMisses.Add(1)
+		if err := c.fetch(key, e.Hash); err != nil {
+			log.Printf("[ERROR] %s (cache: %s)\n", key, err.Error())
+			return c.store.Cat(key, offset, length)
+		}
+	}
+	c.touch(keyRoot(key), e.Hash, e.Size)
+
+	r, err := c.d.ReadStream(e.Hash, false)
+	if err != nil {
+		// evicted between the check and the read
+		return c.store.Cat(key, offset, length)
+	}
+	if _, err := io.CopyN(ioutil.Discard, r, offset); err != nil {
+		r.Close()
+		return nil, err
+	}
+	if length >= 0 {
+		return readCloser{io.LimitReader(r, length), r}, nil
+	}
+	return r, nil
+}
@@ -0,0 +1,294 @@
+package main
+
+import (
+	"bufio"
+	"crypto/aes"
+	"crypto/cipher"
+	"crypto/rand"
+	"encoding/base64"
+	"encoding/binary"
+	"encoding/json"
+	"html/template"
+	"io"
+	"log"
+	"net/http"
+	"net/url"
+	"strings"
+)
+
+// sealChunkSize is how much plaintext goes in each sealed chunk of an
+// encrypted paste.
+const sealChunkSize = 64 * 1024
+
+// sealer transforms the body of a single paste before it's stored, eg.
+// encrypting it. A nil sealer stores it as is.
+type sealer func(io.Reader) io.Reader
+
+func (s sealer) seal(r io.Reader) io.Reader {
+	if s == nil {
+		return r
+	}
+	return s(r)
+}
+
+// sealing is what's done to a single paste on its way into the store. An
+// upload that asks to be encrypted fails if it isn't a single paste, while
+// compression is only applied where it can be.
+type sealing struct {
+	encrypt  sealer
+	compress sealer
+}
+
+func (s sealing) seal(r io.Reader) io.Reader {
+	return s.encrypt.seal(s.compress.seal(r))
+}
+
+// closeSealed stops a sealer that's still writing, once the paste it was
+// sealing is stored or has failed.
+func closeSealed(r io.Reader) {
+	if p, ok := r.(pipeReader); ok {
+		p.Close()
+	}
+}
+
+// pipeReader is the read end of a sealer that writes in its own goroutine,
+// for encoders that only work on writers.
+type pipeReader struct {
+	*io.PipeReader
+	done chan struct{}
+}
+
+// pipeSeal runs seal from r into the returned reader as it's read.
+func pipeSeal(r io.Reader, seal func(w io.Writer, r io.Reader) error) io.Reader {
+	pr, pw := io.Pipe()
+	done := make(chan struct{})
+	go func() {
+		defer close(done)
+		pw.CloseWithError(seal(pw, r))
+	}()
+	return pipeReader{pr, done}
+}
+
+// Close fails the sealer's next write and waits for it to stop, so it never
+// reads from an upload that's finished.
+func (p pipeReader) Close() error {
+	err := p.PipeReader.Close()
+	<-p.done
+	return err
+}
+
+// errReader fails every read with err.
+type errReader struct{ err error }
+
+func (e errReader) Read([]byte) (int, error) { return 0, e.err }
+
+// newSecret makes a random paste key, encoded to go in a url.
+func newSecret() (string, error) {
+	b := make([]byte, 32)
+	if _, err := rand.Read(b); err != nil {
+		return "", err
+	}
+	return base64.RawURLEncoding.EncodeToString(b), nil
+}
+
+// secretAEAD is the cipher for a paste key from a url.
+func secretAEAD(secret string) (cipher.AEAD, error) {
+	key, err := base64.RawURLEncoding.DecodeString(secret)
+	if err != nil || len(key) != 32 {
+		return nil, decryptDenied{}
+	}
+	return keyAEAD(key)
+}
+
+func keyAEAD(key []byte) (cipher.AEAD, error) {
+	block, err := aes.NewCipher(key)
+	if err != nil {
+		return nil, err
+	}
+	return cipher.NewGCM(block)
+}
+
+// chunkNonce numbers the chunks of a paste and marks the last one, so chunks
+// can't be reordered or the paste cut short. Every paste has its own key, so
+// the nonces never repeat under one.
+func chunkNonce(n uint64, last bool) []byte {
+	nonce := make([]byte, 12)
+	binary.BigEndian.PutUint64(nonce[3:11], n)
+	if last {
+		nonce[11] = 1
+	}
+	return nonce
+}
+
+// encryptReader seals a paste with AES-GCM as it streams through, in chunks
+// of sealChunkSize. The plaintext is size checked by writePaste, so the
+// overhead of the tags doesn't count against the paste.
+type encryptReader struct {
+	r     *bufio.Reader
+	aead  cipher.AEAD
+	n     uint64 // chunks sealed
+	total int64  // plaintext read
+	out   []byte // sealed bytes not read yet
+	done  bool
+}
+
+// encryptSealer is the sealer for encrypted uploads.
+func encryptSealer(aead cipher.AEAD) sealer {
+	return func(r io.Reader) io.Reader {
+		return &encryptReader{r: bufio.NewReader(r), aead: aead}
+	}
+}
+
+func (e *encryptReader) Read(b []byte) (int, error) {
+	for len(e.out) == 0 {
+		if e.done {
+			return 0, io.EOF
+		}
+		if err := e.sealChunk(); err != nil {
+			return 0, err
+		}
+	}
+	n := copy(b, e.out)
+	e.out = e.out[n:]
+	return n, nil
+}
+
+func (e *encryptReader) sealChunk() error {
+	chunk := make([]byte, sealChunkSize)
+	n, err := io.ReadFull(e.r, chunk)
+	switch {
+	case err == io.EOF || err == io.ErrUnexpectedEOF:
+		e.done = true
+	case err != nil:
+		return err
+	default:
+		// a full chunk is the last one when nothing follows it
+		if _, err := e.r.Peek(1); err == io.EOF {
+			e.done = true
+		} else if err != nil {
+			return err
+		}
+	}
+	e.total += int64(n)
+	if e.done && e.total < minPasteSize {
+		// the ciphertext is never too small, so check the plaintext
+		return pasteTooSmall{}
+	}
+	e.out = e.aead.Seal(nil, chunkNonce(e.n, e.done), chunk[:n], nil)
+	e.n++
+	return nil
+}
+
+// decryptPaste opens a paste sealed by encryptReader.
+func decryptPaste(sealed []byte, aead cipher.AEAD) (plain []byte, err error) {
+	size := sealChunkSize + aead.Overhead()
+	for n := uint64(0); ; n++ {
+		last := len(sealed) <= size
+		chunk := sealed
+		if !last {
+			chunk = sealed[:size]
+		}
+		if plain, err = aead.Open(plain, chunkNonce(n, last), chunk, nil); err != nil {
+			return nil, decryptDenied{}
+		}
+		if last {
+			return plain, nil
+		}
+		sealed = sealed[size:]
+	}
+}
+
+// takeSecret removes the paste key from the query of a read and returns it,
+// so the rest of the query still picks how the paste is shown.
+func takeSecret(req *http.Request) string {
+	var secret string
+	var rest []string
+	for _, p := range strings.Split(req.URL.RawQuery, "&") {
+		if strings.HasPrefix(p, "key=") {
+			secret, _ = url.QueryUnescape(p[len("key="):])
+		} else if p != "" {
+			rest = append(rest, p)
+		}
+	}
+	req.URL.RawQuery = strings.Join(rest, "&")
+	return secret
+}
+
+const zkPageHTML = `<!doctype html>
+<html>
+<head>
+  <title>{{.Key}}</title>
+  <style>
+    body { background-color: #000000; color: #fff; font-family: monospace; }
+    pre { white-space: pre-wrap; word-break: break-all; }
+  </style>
+</head>
+<body>
+  <pre id="paste">decrypting...</pre>
+  <script>
+    const sealed = {{.Sealed}};
+    const out = document.getElementById("paste");
+    function unb64(s) {
+      return Uint8Array.from(atob(s.replaceAll("-", "+").replaceAll("_", "/")), (c) => c.charCodeAt(0));
+    }
+    (async () => {
+      try {
+        const key = await crypto.subtle.importKey("raw", unb64(location.hash.slice(1)), "AES-GCM", false, ["decrypt"]);
+        const plain = await crypto.subtle.decrypt({name: "AES-GCM", iv: unb64(sealed.iv)}, key, unb64(sealed.ct));
+        out.textContent = new TextDecoder().decode(plain);
+      } catch (e) {
+        out.textContent = "can't decrypt, the key after the # in the url is missing or wrong";
+      }
+    })();
+  </script>
+</body>
+</html>
+`
+
+var zkPageTmpl = template.Must(template.New("zk").Parse(zkPageHTML))
+
+// zkPaste is a paste encrypted by the web form with AES-GCM. The server only
+// ever sees this, the key stays in the url fragment.
+type zkPaste struct {
+	V  int    `json:"v"`
+	IV string `json:"iv"` // base64url
+	CT string `json:"ct"` // base64url ciphertext and tag
+}
+
+// parseZKPaste checks that a paste looks like what the web form sends.
+func parseZKPaste(b []byte) (p zkPaste, ok bool) {
+	if json.Unmarshal(b, &p) != nil || p.V != 1 {
+		return p, false
+	}
+	iv, err := base64.RawURLEncoding.DecodeString(p.IV)
+	if err != nil || len(iv) != 12 {
+		return p, false
+	}
+	ct, err := base64.RawURLEncoding.DecodeString(p.CT)
+	return p, err == nil && len(ct) >= 16
+}
+
+// zkPage serves the page that decrypts a browser encrypted paste with the
+// key from the url fragment.
+func (h *handler) zkPage(w http.ResponseWriter, key string) {
+	b, err := readPaste(h.store, key)
+	if err != nil {
+		http.Error(w, err.Error(), http.StatusInternalServerError)
+		log.Printf("[ERROR] %s (%s)\n", key, err.Error())
+		return
+	}
+	sealed, ok := parseZKPaste(b)
+	if !ok {
+		http.Error(w, "not a browser encrypted paste", http.StatusInternalServerError)
+		log.Printf("[ERROR] %s (bad zk paste)\n", key)
+		return
+	}
+	w.Header().Set("Content-Type", "text/html; charset=utf-8")
+	err = zkPageTmpl.Execute(w, struct {
+		Key    string
+		Sealed zkPaste
+	}{key, sealed})
+	if err != nil {
+		log.Printf("[ERROR] %s (zk page: %s)\n", key, err.Error())
+	}
+}
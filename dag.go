@@ -0,0 +1,626 @@
+package main
+
+import (
+	"encoding/binary"
+	"errors"
+	"io"
+	"io/fs"
+	"os"
+	"path"
+	"path/filepath"
+	"sort"
+	"strings"
+	"sync"
+
+	cid "github.com/ipfs/go-cid"
+	mh "github.com/multiformats/go-multihash"
+)
+
+/* --- unixfs dag ---
+ * A minimal encoder/decoder for the dag-pb + unixfs format go-ipfs produces
+ * with `ipfs add` defaults (cidv0, 256 KiB chunks, balanced layout, no raw
+ * leaves). Backends that aren't an ipfs daemon store these blocks so keys
+ * stay interchangeable with real ipfs hashes.
+ */
+const (
+	chunkSize    = 256 * 1024 // size-262144 chunker
+	maxDagLinks  = 174        // balanced layout fan out
+	unixfsDir    = 1
+	unixfsFile   = 2
+	pbLinksField = 2
+	pbDataField  = 1
+	pinPrefix    = "pin-" // empty marker blocks naming pinned roots
+)
+
+var errBadBlock = errors.New("malformed dag-pb block")
+
+// blockstore holds raw dag blocks keyed by their cid string.
+type blockstore interface {
+	Get(key string) ([]byte, error)
+	Put(key string, data []byte) error
+	Delete(key string) error
+	Keys() ([]string, error)
+}
+
+type dagLink struct {
+	Hash  cid.Cid
+	Name  string
+	Tsize uint64 // cumulative size of the linked subtree
+}
+
+type dagNode struct {
+	Links []dagLink
+	Data  []byte
+}
+
+type unixfsData struct {
+	Type       uint64
+	Data       []byte
+	FileSize   uint64
+	BlockSizes []uint64
+}
+
+/* --- protobuf --- */
+
+func appendUvarint(b []byte, v uint64) []byte {
+	var buf [binary.MaxVarintLen64]byte
+	n := binary.PutUvarint(buf[:], v)
+	return append(b, buf[:n]...)
+}
+
+func appendPbVarint(b []byte, field int, v uint64) []byte {
+	b = appendUvarint(b, uint64(field)<<3)
+	return appendUvarint(b, v)
+}
+
+func appendPbBytes(b []byte, field int, v []byte) []byte {
+	b = appendUvarint(b, uint64(field)<<3|2)
+	b = appendUvarint(b, uint64(len(v)))
+	return append(b, v...)
+}
+
+// readPb calls fn for every varint or length delimited field in b.
+func readPb(b []byte, fn func(field int, v uint64, data []byte) error) error {
+	for len(b) > 0 {
+		key, n := binary.Uvarint(b)
+		if n <= 0 {
+			return errBadBlock
+		}
+		b = b[n:]
+		field := int(key >> 3)
+		switch key & 7 {
+		case 0:
+			v, n := binary.Uvarint(b)
+			if n <= 0 {
+				return errBadBlock
+			}
+			b = b[n:]
+			if err := fn(field, v, nil); err != nil {
+				return err
+			}
+		case 2:
+			l, n := binary.Uvarint(b)
+			if n <= 0 || uint64(len(b)-n) < l {
+				return errBadBlock
+			}
+			data := b[n : n+int(l)]
+			b = b[n+int(l):]
+			if err := fn(field, 0, data); err != nil {
+				return err
+			}
+		default:
+			return errBadBlock
+		}
+	}
+	return nil
+}
+
+func (d unixfsData) marshal() []byte {
+	b := appendPbVarint(nil, 1, d.Type)
+	if d.Data != nil {
+		b = appendPbBytes(b, 2, d.Data)
+	}
+	if d.Type == unixfsFile {
+		b = appendPbVarint(b, 3, d.FileSize)
+	}
+	for _, s := range d.BlockSizes {
+		b = appendPbVarint(b, 4, s)
+	}
+	return b
+}
+
+func unmarshalUnixfs(b []byte) (d unixfsData, err error) {
+	err = readPb(b, func(field int, v uint64, data []byte) error {
+		switch field {
+		case 1:
+			d.Type = v
+		case 2:
+			d.Data = data
+		case 3:
+			d.FileSize = v
+		case 4:
+			d.BlockSizes = append(d.BlockSizes, v)
+		}
+		return nil
+	})
+	return
+}
+
+// marshal encodes the node in canonical dag-pb form (links before data).
+func (n dagNode) marshal() []byte {
+	var b []byte
+	for _, l := range n.Links {
+		var lb []byte
+		lb = appendPbBytes(lb, 1, l.Hash.Bytes())
+		lb = appendPbBytes(lb, 2, []byte(l.Name))
+		lb = appendPbVarint(lb, 3, l.Tsize)
+		b = appendPbBytes(b, pbLinksField, lb)
+	}
+	return appendPbBytes(b, pbDataField, n.Data)
+}
+
+func unmarshalNode(b []byte) (n dagNode, err error) {
+	err = readPb(b, func(field int, _ uint64, data []byte) error {
+		switch field {
+		case pbDataField:
+			n.Data = data
+		case pbLinksField:
+			var l dagLink
+			err := readPb(data, func(field int, v uint64, data []byte) error {
+				var err error
+				switch field {
+				case 1:
+					l.Hash, err = cid.Cast(data)
+				case 2:
+					l.Name = string(data)
+				case 3:
+					l.Tsize = v
+				}
+				return err
+			})
+			if err != nil {
+				return err
+			}
+			n.Links = append(n.Links, l)
+		}
+		return nil
+	})
+	return
+}
+
+/* --- dag store --- */
+
+// dagStore implements store on top of a plain blockstore.
+type dagStore struct {
+	blocks blockstore
+	gcLock sync.RWMutex // held for writing by GC, reading by adds
+}
+
+// dagEntry is the result of adding a node: its link and unixfs file size.
+type dagEntry struct {
+	link     dagLink
+	fileSize uint64
+}
+
+func (s *dagStore) putNode(n dagNode) (dagLink, error) {
+	raw := n.marshal()
+	hash, err := mh.Sum(raw, mh.SHA2_256, -1)
+	if err != nil {
+		return dagLink{}, err
+	}
+	c := cid.NewCidV0(hash)
+	size := uint64(len(raw))
+	for _, l := range n.Links {
+		size += l.Tsize
+	}
+	return dagLink{Hash: c, Tsize: size}, s.blocks.Put(c.String(), raw)
+}
+
+func (s *dagStore) getNode(c cid.Cid) (dagNode, unixfsData, error) {
+	raw, err := s.blocks.Get(c.String())
+	if err != nil {
+		return dagNode{}, unixfsData{}, err
+	}
+	n, err := unmarshalNode(raw)
+	if err != nil {
+		return n, unixfsData{}, err
+	}
+	d, err := unmarshalUnixfs(n.Data)
+	return n, d, err
+}
+
+func (s *dagStore) addFile(r io.Reader) (dagEntry, error) {
+	var level []dagEntry
+	buf := make([]byte, chunkSize)
+	for {
+		n, err := io.ReadFull(r, buf)
+		if err == io.EOF && len(level) > 0 {
+			break
+		} else if err != nil && err != io.ErrUnexpectedEOF && err != io.EOF {
+			return dagEntry{}, err
+		}
+		data := make([]byte, n)
+		copy(data, buf[:n])
+		fs := unixfsData{Type: unixfsFile, Data: data, FileSize: uint64(n)}
+		if n == 0 {
+			fs.Data = nil
+		}
+		l, perr := s.putNode(dagNode{Data: fs.marshal()})
+		if perr != nil {
+			return dagEntry{}, perr
+		}
+		level = append(level, dagEntry{l, uint64(n)})
+		if err != nil {
+			break
+		}
+	}
+
+	// balanced layout: pack leaves into parents until a single root remains
+	for len(level) > 1 {
+		var next []dagEntry
+		for i := 0; i < len(level); i += maxDagLinks {
+			end := i + maxDagLinks
+			if end > len(level) {
+				end = len(level)
+			}
+			var node dagNode
+			fs := unixfsData{Type: unixfsFile}
+			for _, e := range level[i:end] {
+				node.Links = append(node.Links, e.link)
+				fs.BlockSizes = append(fs.BlockSizes, e.fileSize)
+				fs.FileSize += e.fileSize
+			}
+			node.Data = fs.marshal()
+			l, err := s.putNode(node)
+			if err != nil {
+				return dagEntry{}, err
+			}
+			next = append(next, dagEntry{l, fs.FileSize})
+		}
+		level = next
+	}
+	return level[0], nil
+}
+
+func (s *dagStore) addDir(dir string) (dagLink, error) {
+	entries, err := os.ReadDir(dir)
+	if err != nil {
+		return dagLink{}, err
+	}
+	node := dagNode{Data: unixfsData{Type: unixfsDir}.marshal()}
+	for _, e := range entries {
+		var l dagLink
+		if e.IsDir() {
+			l, err = s.addDir(path.Join(dir, e.Name()))
+		} else {
+			var f *os.File
+			f, err = os.Open(path.Join(dir, e.Name()))
+			if err != nil {
+				return dagLink{}, err
+			}
+			var fe dagEntry
+			fe, err = s.addFile(f)
+			f.Close()
+			l = fe.link
+		}
+		if err != nil {
+			return dagLink{}, err
+		}
+		l.Name = e.Name()
+		node.Links = append(node.Links, l)
+	}
+	sort.SliceStable(node.Links, func(i, j int) bool { return node.Links[i].Name < node.Links[j].Name })
+	return s.putNode(node)
+}
+
+// resolve walks a "<cid>/<name>/..." key down to the node it names.
+func (s *dagStore) resolve(key string) (cid.Cid, dagNode, unixfsData, error) {
+	parts := strings.Split(strings.Trim(key, "/"), "/")
+	c, err := cid.Decode(parts[0])
+	if err != nil {
+		return c, dagNode{}, unixfsData{}, err
+	}
+	n, d, err := s.getNode(c)
+	for _, name := range parts[1:] {
+		if err != nil {
+			return c, n, d, err
+		}
+		if d.Type != unixfsDir {
+			return c, n, d, os.ErrNotExist
+		}
+		found := false
+		for _, l := range n.Links {
+			if l.Name == name {
+				c = l.Hash
+				n, d, err = s.getNode(c)
+				found = true
+				break
+			}
+		}
+		if !found {
+			return c, n, d, os.ErrNotExist
+		}
+	}
+	return c, n, d, err
+}
+
+// writeFile streams length bytes of the file rooted at n, starting at offset,
+// to w. Subtrees before offset are skipped using the unixfs block sizes. A
+// negative length reads to the end; the unread remainder is returned.
+func (s *dagStore) writeFile(w io.Writer, n dagNode, d unixfsData, offset, length int64) (int64, error) {
+	if offset < int64(len(d.Data)) {
+		chunk := d.Data[offset:]
+		if length >= 0 && int64(len(chunk)) > length {
+			chunk = chunk[:length]
+		}
+		if _, err := w.Write(chunk); err != nil {
+			return length, err
+		}
+		if length >= 0 {
+			length -= int64(len(chunk))
+		}
+		offset = 0
+	} else {
+		offset -= int64(len(d.Data))
+	}
+
+	for i, l := range n.Links {
+		if length == 0 {
+			break
+		}
+		if i < len(d.BlockSizes) && offset >= int64(d.BlockSizes[i]) {
+			offset -= int64(d.BlockSizes[i])
+			continue
+		}
+		cn, cd, err := s.getNode(l.Hash)
+		if err != nil {
+			return length, err
+		}
+		if length, err = s.writeFile(w, cn, cd, offset, length); err != nil {
+			return length, err
+		}
+		offset = 0
+	}
+	return length, nil
+}
+
+func (s *dagStore) Add(r io.Reader) (string, error) {
+	s.gcLock.RLock()
+	defer s.gcLock.RUnlock()
+	e, err := s.addFile(r)
+	if err != nil {
+		return "", err
+	}
+	return e.link.Hash.String(), s.blocks.Put(pinPrefix+e.link.Hash.String(), nil)
+}
+
+func (s *dagStore) AddDir(dir string) (string, error) {
+	s.gcLock.RLock()
+	defer s.gcLock.RUnlock()
+	l, err := s.addDir(dir)
+	if err != nil {
+		return "", err
+	}
+	return l.Hash.String(), s.blocks.Put(pinPrefix+l.Hash.String(), nil)
+}
+
+func (s *dagStore) Unpin(hash string) error {
+	c, err := cid.Decode(hash)
+	if err != nil {
+		return err
+	}
+	return s.blocks.Delete(pinPrefix + c.String())
+}
+
+// GC is a mark and sweep over the blockstore, keeping pin markers and every
+// block reachable from a pinned root.
+func (s *dagStore) GC() error {
+	s.gcLock.Lock()
+	defer s.gcLock.Unlock()
+
+	keys, err := s.blocks.Keys()
+	if err != nil {
+		return err
+	}
+	live := make(map[string]bool)
+	for _, key := range keys {
+		if strings.HasPrefix(key, pinPrefix) {
+			live[key] = true
+			if err := s.mark(strings.TrimPrefix(key, pinPrefix), live); err != nil {
+				return err
+			}
+		}
+	}
+	for _, key := range keys {
+		if !live[key] {
+			if err := s.blocks.Delete(key); err != nil {
+				return err
+			}
+		}
+	}
+	return nil
+}
+
+func (s *dagStore) mark(key string, live map[string]bool) error {
+	if live[key] {
+		return nil
+	}
+	live[key] = true
+	raw, err := s.blocks.Get(key)
+	if errors.Is(err, os.ErrNotExist) {
+		return nil
+	} else if err != nil {
+		return err
+	}
+	n, err := unmarshalNode(raw)
+	if err != nil {
+		return err
+	}
+	for _, l := range n.Links {
+		if err := s.mark(l.Hash.String(), live); err != nil {
+			return err
+		}
+	}
+	return nil
+}
+
+func (s *dagStore) Stat(key string) (entry, error) {
+	c, n, d, err := s.resolve(key)
+	if err != nil {
+		return entry{}, err
+	}
+	e := entry{Name: path.Base(key), Hash: c.String(), Size: int64(d.FileSize), IsDir: d.Type == unixfsDir}
+	if e.IsDir {
+		for _, l := range n.Links {
+			e.Size += int64(l.Tsize)
+		}
+	}
+	return e, nil
+}
+
+func (s *dagStore) Ls(key string) ([]entry, error) {
+	_, n, d, err := s.resolve(key)
+	if err != nil {
+		return nil, err
+	}
+	if d.Type != unixfsDir {
+		return nil, errors.New("not a directory")
+	}
+	entries := make([]entry, 0, len(n.Links))
+	for _, l := range n.Links {
+		_, ld, err := s.getNode(l.Hash)
+		if err != nil {
+			return nil, err
+		}
+		e := entry{Name: l.Name, Hash: l.Hash.String(), Size: int64(ld.FileSize), IsDir: ld.Type == unixfsDir}
+		if e.IsDir {
+			e.Size = int64(l.Tsize)
+		}
+		entries = append(entries, e)
+	}
+	return entries, nil
+}
+
+func (s *dagStore) Cat(key string, offset, length int64) (io.ReadCloser, error) {
+	_, n, d, err := s.resolve(key)
+	if err != nil {
+		return nil, err
+	}
+	if d.Type != unixfsFile {
+		return nil, errors.New("not a file")
+	}
+	pr, pw := io.Pipe()
+	go func() {
+		_, err := s.writeFile(pw, n, d, offset, length)
+		pw.CloseWithError(err)
+	}()
+	return pr, nil
+}
+
+// NewName can't make a real ipns name without a keystore and a network to
+// publish to, so names are derived from the key and only resolve locally.
+func (s *dagStore) NewName(key string) (string, error) {
+	return "k" + sha256Hex([]byte(key))[:40], nil
+}
+
+// Publish has nothing to do, the server's name index is the only record.
+func (s *dagStore) Publish(key, hash string) error { return nil }
+
+/* --- block backends --- */
+
+// memBlocks keeps blocks in process memory. It backs the in-process fake
+// ipfs used by the tests, and -store mem for throwaway instances.
+type memBlocks struct {
+	mu     sync.RWMutex
+	blocks map[string][]byte
+}
+
+func newMemStore() *dagStore {
+	return &dagStore{blocks: &memBlocks{blocks: make(map[string][]byte)}}
+}
+
+func (b *memBlocks) Get(key string) ([]byte, error) {
+	b.mu.RLock()
+	defer b.mu.RUnlock()
+	data, ok := b.blocks[key]
+	if !ok {
+		return nil, os.ErrNotExist
+	}
+	return data, nil
+}
+
+func (b *memBlocks) Put(key string, data []byte) error {
+	b.mu.Lock()
+	defer b.mu.Unlock()
+	b.blocks[key] = data
+	return nil
+}
+
+func (b *memBlocks) Delete(key string) error {
+	b.mu.Lock()
+	defer b.mu.Unlock()
+	delete(b.blocks, key)
+	return nil
+}
+
+func (b *memBlocks) Keys() (keys []string, err error) {
+	b.mu.RLock()
+	defer b.mu.RUnlock()
+	for key := range b.blocks {
+		keys = append(keys, key)
+	}
+	return
+}
+
+// diskBlocks keeps one file per block, sharded on the next-to-last two
+// characters of the cid like go-ipfs' flatfs.
+type diskBlocks struct {
+	root string
+}
+
+func (b *diskBlocks) path(key string) string {
+	return path.Join(b.root, key[len(key)-3:len(key)-1], key)
+}
+
+func (b *diskBlocks) Get(key string) ([]byte, error) {
+	return os.ReadFile(b.path(key))
+}
+
+func (b *diskBlocks) Put(key string, data []byte) error {
+	p := b.path(key)
+	if _, err := os.Stat(p); err == nil {
+		return nil // content addressed, already have it
+	}
+	if err := os.MkdirAll(path.Dir(p), 0755); err != nil {
+		return err
+	}
+	tmp := p + ".tmp" + newID(urlLength)
+	if err := os.WriteFile(tmp, data, 0644); err != nil {
+		return err
+	}
+	return os.Rename(tmp, p)
+}
+
+func (b *diskBlocks) Delete(key string) error {
+	err := os.Remove(b.path(key))
+	if os.IsNotExist(err) {
+		return nil
+	}
+	return err
+}
+
+func (b *diskBlocks) Keys() (keys []string, err error) {
+	err = filepath.WalkDir(b.root, func(p string, e fs.DirEntry, err error) error {
+		if err != nil {
+			return err
+		}
+		if !e.IsDir() && !strings.Contains(e.Name(), ".tmp") {
+			keys = append(keys, e.Name())
+		}
+		return nil
+	})
+	if os.IsNotExist(err) {
+		err = nil
+	}
+	return
+}
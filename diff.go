@@ -0,0 +1,420 @@
+package main
+
+import (
+	"bytes"
+	"fmt"
+	"html/template"
+	"log"
+	"net/http"
+	"strconv"
+	"strings"
+	"time"
+
+	"github.com/gorilla/mux"
+	"github.com/sergi/go-diff/diffmatchpatch"
+	"github.com/shurcooL/highlight_diff"
+	"github.com/sourcegraph/annotate"
+)
+
+const (
+	diffContext = 3           // unchanged lines around each hunk
+	maxDiffSize = 1024 * 1024 // 1 MB, the largest paste that will be diffed
+)
+
+const diffHTMLPrefix = `<!doctype html>
+<html>
+<head>
+  <title>{{.}}</title>
+  <style>
+    body { background-color: #000000; color: #fff; }
+    pre { font-family: monospace; }
+    .gi { color: #8f8; background-color: #131; }
+    .gd { color: #f88; background-color: #311; }
+    .gu { color: #8ab4f8; }
+    .x { font-weight: bold; }
+  </style>
+</head>
+<body>
+<pre>`
+
+const diffHTMLSuffix = `</pre>
+</body>
+</html>
+`
+
+const sideBySideHTML = `<!doctype html>
+<html>
+<head>
+  <title>{{.Title}}</title>
+  <style>
+    body { background-color: #000000; color: #fff; font-family: monospace; }
+    table { border-collapse: collapse; width: 100%; table-layout: fixed; }
+    td { white-space: pre-wrap; word-break: break-all; vertical-align: top; padding: 0 8px; }
+    td.n { width: 4em; text-align: right; color: #888; }
+    .gi { background-color: #131; }
+    .gd { background-color: #311; }
+    .gi .x { background-color: #363; }
+    .gd .x { background-color: #633; }
+  </style>
+</head>
+<body>
+  <h3>{{.Title}}</h3>
+  <table>
+    {{range .Rows}}<tr><td class="n">{{.LeftNo}}</td><td class="{{.LeftClass}}">{{.Left}}</td><td class="n">{{.RightNo}}</td><td class="{{.RightClass}}">{{.Right}}</td></tr>
+    {{end}}
+  </table>
+</body>
+</html>
+`
+
+var (
+	diffHTMLTmpl   = template.Must(template.New("diff").Parse(diffHTMLPrefix))
+	sideBySideTmpl = template.Must(template.New("sidebyside").Parse(sideBySideHTML))
+)
+
+// diffLine is one line of a line diff: ' ' kept, '-' removed or '+' added.
+type diffLine struct {
+	op   byte
+	text string
+}
+
+// splitLines splits text after each newline, the last line may lack one.
+func splitLines(text string) []string {
+	lines := strings.SplitAfter(text, "\n")
+	if lines[len(lines)-1] == "" {
+		lines = lines[:len(lines)-1]
+	}
+	return lines
+}
+
+// diffLines diffs a and b line by line. With ignoreSpace, lines that only
+// differ in whitespace count as the same, and b's version is kept.
+func diffLines(a, b string, ignoreSpace bool) []diffLine {
+	la, lb := splitLines(a), splitLines(b)
+	na, nb := a, b
+	if ignoreSpace {
+		normalize := func(lines []string) string {
+			var buf strings.Builder
+			for _, l := range lines {
+				buf.WriteString(strings.Join(strings.Fields(l), " ") + "\n")
+			}
+			return buf.String()
+		}
+		na, nb = normalize(la), normalize(lb)
+	}
+
+	dmp := diffmatchpatch.New()
+	ca, cb, lines := dmp.DiffLinesToChars(na, nb)
+	diffs := dmp.DiffCharsToLines(dmp.DiffMain(ca, cb, false), lines)
+
+	// the diff ran on the normalized text, take the lines themselves from
+	// the originals, which have the same number of lines
+	var out []diffLine
+	i, j := 0, 0
+	for _, d := range diffs {
+		n := len(splitLines(d.Text))
+		switch d.Type {
+		case diffmatchpatch.DiffDelete:
+			for _, l := range la[i : i+n] {
+				out = append(out, diffLine{'-', l})
+			}
+			i += n
+		case diffmatchpatch.DiffInsert:
+			for _, l := range lb[j : j+n] {
+				out = append(out, diffLine{'+', l})
+			}
+			j += n
+		default:
+			for _, l := range lb[j : j+n] {
+				out = append(out, diffLine{' ', l})
+			}
+			i, j = i+n, j+n
+		}
+	}
+	return out
+}
+
+// unifiedDiff renders a line diff as a unified diff, empty when nothing
+// changed.
+func unifiedDiff(aName, bName string, lines []diffLine) string {
+	var buf bytes.Buffer
+	for start := 0; start < len(lines); {
+		// find the next change and grow the hunk until changes are more
+		// than two contexts apart
+		first := start
+		for first < len(lines) && lines[first].op == ' ' {
+			first++
+		}
+		if first == len(lines) {
+			break
+		}
+		last := first
+		for i := first; i < len(lines) && i-last <= 2*diffContext; i++ {
+			if lines[i].op != ' ' {
+				last = i
+			}
+		}
+		from, to := max(first-diffContext, start), min(last+diffContext+1, len(lines))
+
+		// line numbers of the hunk in a and b
+		aLine, bLine := 1, 1
+		for _, l := range lines[:from] {
+			if l.op != '+' {
+				aLine++
+			}
+			if l.op != '-' {
+				bLine++
+			}
+		}
+		aCount, bCount := 0, 0
+		for _, l := range lines[from:to] {
+			if l.op != '+' {
+				aCount++
+			}
+			if l.op != '-' {
+				bCount++
+			}
+		}
+
+		if buf.Len() == 0 {
+			fmt.Fprintf(&buf, "--- %s\n+++ %s\n", aName, bName)
+		}
+		fmt.Fprintf(&buf, "@@ -%s +%s @@\n", hunkRange(aLine, aCount), hunkRange(bLine, bCount))
+		for _, l := range lines[from:to] {
+			buf.WriteByte(l.op)
+			buf.WriteString(l.text)
+			if !strings.HasSuffix(l.text, "\n") {
+				buf.WriteString("\n\\ No newline at end of file\n")
+			}
+		}
+		start = to
+	}
+	return buf.String()
+}
+
+// hunkRange formats the start,count of a hunk the way diff -u does.
+func hunkRange(start, count int) string {
+	if count == 0 {
+		start--
+	}
+	if count == 1 {
+		return fmt.Sprint(start)
+	}
+	return fmt.Sprintf("%d,%d", start, count)
+}
+
+// diffHTML renders a unified diff as a highlighted html page.
+func diffHTML(title, unified string) ([]byte, error) {
+	src := []byte(unified)
+	anns, err := highlight_diff.Annotate(src)
+	if err != nil {
+		return nil, err
+	}
+	body, err := annotate.Annotate(src, anns, template.HTMLEscape)
+	if err != nil {
+		return nil, err
+	}
+
+	var buf bytes.Buffer
+	if err := diffHTMLTmpl.Execute(&buf, title); err != nil {
+		return nil, err
+	}
+	buf.Write(body)
+	buf.WriteString(diffHTMLSuffix)
+	return buf.Bytes(), nil
+}
+
+func max(a, b int) int {
+	if a > b {
+		return a
+	}
+	return b
+}
+
+func min(a, b int) int {
+	if a < b {
+		return a
+	}
+	return b
+}
+
+// diffRow is a line of a side by side diff. A side is empty when the line
+// only exists on the other one.
+type diffRow struct {
+	LeftNo, RightNo       string
+	Left, Right           template.HTML
+	LeftClass, RightClass string
+}
+
+// sideBySide lays a line diff out in rows, pairing each run of removed lines
+// with the lines added in their place.
+func sideBySide(lines []diffLine) []diffRow {
+	var rows []diffRow
+	an, bn := 1, 1
+	for i := 0; i < len(lines); {
+		if lines[i].op == ' ' {
+			text := escapeLine(lines[i].text)
+			rows = append(rows, diffRow{strconv.Itoa(an), strconv.Itoa(bn), text, text, "", ""})
+			an, bn, i = an+1, bn+1, i+1
+			continue
+		}
+
+		var del, ins []string
+		for ; i < len(lines) && lines[i].op == '-'; i++ {
+			del = append(del, lines[i].text)
+		}
+		for ; i < len(lines) && lines[i].op == '+'; i++ {
+			ins = append(ins, lines[i].text)
+		}
+		for k := 0; k < len(del) || k < len(ins); k++ {
+			var r diffRow
+			switch {
+			case k < len(del) && k < len(ins):
+				r.Left, r.Right = wordDiff(del[k], ins[k])
+			case k < len(del):
+				r.Left = escapeLine(del[k])
+			default:
+				r.Right = escapeLine(ins[k])
+			}
+			if k < len(del) {
+				r.LeftNo, r.LeftClass = strconv.Itoa(an), "gd"
+				an++
+			}
+			if k < len(ins) {
+				r.RightNo, r.RightClass = strconv.Itoa(bn), "gi"
+				bn++
+			}
+			rows = append(rows, r)
+		}
+	}
+	return rows
+}
+
+func escapeLine(line string) template.HTML {
+	return template.HTML(template.HTMLEscapeString(strings.TrimSuffix(line, "\n")))
+}
+
+// wordDiff marks the parts of a changed line that were removed from a and
+// added in b.
+func wordDiff(a, b string) (template.HTML, template.HTML) {
+	dmp := diffmatchpatch.New()
+	diffs := dmp.DiffCleanupSemantic(dmp.DiffMain(strings.TrimSuffix(a, "\n"), strings.TrimSuffix(b, "\n"), false))
+	var left, right strings.Builder
+	for _, d := range diffs {
+		text := template.HTMLEscapeString(d.Text)
+		switch d.Type {
+		case diffmatchpatch.DiffDelete:
+			left.WriteString(`<span class="x">` + text + `</span>`)
+		case diffmatchpatch.DiffInsert:
+			right.WriteString(`<span class="x">` + text + `</span>`)
+		default:
+			left.WriteString(text)
+			right.WriteString(text)
+		}
+	}
+	return template.HTML(left.String()), template.HTML(right.String())
+}
+
+// diffKey finds the file to diff at key. Named pastes are directories
+// holding just the file, so those stand in for it.
+func (h *handler) diffKey(key string) (string, entry, error) {
+	info, err := h.store.Stat(key)
+	if err != nil || !info.IsDir {
+		return key, info, err
+	}
+	entries, err := h.store.Ls(key)
+	if err != nil {
+		return key, info, err
+	} else if len(entries) != 1 || entries[0].IsDir {
+		return key, info, pasteNotFound{}
+	}
+	return key + "/" + entries[0].Name, entries[0], nil
+}
+
+// diff renders the changes from the paste at oldKey to the one at key, as a
+// unified diff for curl and side by side html for browsers, or highlighted
+// unified html with ?unified. ?w ignores changes in whitespace.
+func (h *handler) diff(w http.ResponseWriter, req *http.Request, oldKey, key string) {
+	keys := [2]string{oldKey, key}
+	var texts [2]string
+	for i, k := range keys {
+		hash := strings.SplitN(k, "/", 2)[0]
+		meta, _ := h.index.get(hash)
+		if meta.gone(time.Now()) {
+			http.Error(w, pasteGone{}.Error(), http.StatusGone)
+			log.Printf("[GONE ] %s\n", k)
+			return
+		} else if meta.Encrypted || meta.ClientEncrypted || meta.PasswordSalt != "" || meta.AgeEncrypted {
+			http.Error(w, "encrypted pastes can't be diffed", http.StatusForbidden)
+			return
+		} else if meta.MaxViews > 0 {
+			// a diff would show the paste without counting a view
+			http.Error(w, "view limited pastes can't be diffed", http.StatusForbidden)
+			return
+		}
+		file, info, err := h.diffKey(k)
+		if err != nil {
+			http.Error(w, pasteNotFound{}.Error(), http.StatusNotFound)
+			log.Printf("[ERROR] %s (diff: %s)\n", k, err.Error())
+			return
+		}
+		if info.Size > maxDiffSize {
+			http.Error(w, fmt.Sprintf("too large to diff (maximum size %d bytes)", maxDiffSize), http.StatusRequestEntityTooLarge)
+			return
+		}
+		paste, err := readPaste(h.store, file)
+		if err == nil {
+			paste, err = decodePaste(paste, meta.Encoding)
+		}
+		if err != nil {
+			http.Error(w, err.Error(), http.StatusInternalServerError)
+			log.Printf("[ERROR] %s (diff: %s)\n", file, err.Error())
+			return
+		}
+		keys[i], texts[i] = file, string(paste)
+	}
+
+	query := req.URL.Query()
+	_, ignoreSpace := query["w"]
+	_, unified := query["unified"]
+	lines := diffLines(texts[0], texts[1], ignoreSpace)
+	title := keys[0] + " .. " + keys[1]
+	log.Printf("[DIFF ] %s\n", title)
+
+	var page []byte
+	var err error
+	switch {
+	case !wantsHTML(req):
+		w.Header().Set("Content-Type", "text/plain; charset=utf-8")
+		fmt.Fprint(w, unifiedDiff("a/"+keys[0], "b/"+keys[1], lines))
+		return
+	case unified:
+		page, err = diffHTML(title, unifiedDiff("a/"+keys[0], "b/"+keys[1], lines))
+	default:
+		var buf bytes.Buffer
+		err = sideBySideTmpl.Execute(&buf, struct {
+			Title string
+			Rows  []diffRow
+		}{title, sideBySide(lines)})
+		page = buf.Bytes()
+	}
+	if err != nil {
+		http.Error(w, err.Error(), http.StatusInternalServerError)
+		log.Printf("[ERROR] %s (diff: %s)\n", title, err.Error())
+		return
+	}
+	w.Header().Set("Content-Type", "text/html; charset=utf-8")
+	w.Write(page)
+}
+
+// diffPastes compares any two pastes, by cid, short id or slug.
+func (h *handler) diffPastes(w http.ResponseWriter, req *http.Request) {
+	vars := mux.Vars(req)
+	if useSSL {
+		w.Header().Add("Strict-Transport-Security", "max-age=63072000; includeSubDomains") //ssl lab bullshit
+	}
+	w.Header().Set("X-Content-Type-Options", "nosniff")
+	_, a := h.pasteKey(map[string]string{"hash": vars["a"]})
+	_, b := h.pasteKey(map[string]string{"hash": vars["b"]})
+	h.diff(w, req, a, b)
+}
@@ -0,0 +1,244 @@
+package main
+
+import (
+	"bufio"
+	"bytes"
+	"compress/gzip"
+	"errors"
+	"fmt"
+	"io"
+	"io/ioutil"
+	"log"
+	"mime"
+	"net/http"
+	"strconv"
+	"strings"
+	"time"
+
+	"github.com/andybalholm/brotli"
+	"github.com/klauspost/compress/zstd"
+)
+
+// responseEncodings are the content codings reads can be compressed with,
+// most preferred first.
+var responseEncodings = []string{"br", "zstd", "gzip"}
+
+// newEncoder compresses what's written to w with encoding.
+func newEncoder(encoding string, w io.Writer) (io.WriteCloser, error) {
+	switch encoding {
+	case "gzip":
+		return gzip.NewWriter(w), nil
+	case "br":
+		return brotli.NewWriter(w), nil
+	case "zstd":
+		// the defaults spin up a goroutine per cpu and an 8MB window for
+		// every response, one goroutine and 1MB is plenty for a paste
+		return zstd.NewWriter(w, zstd.WithEncoderConcurrency(1), zstd.WithWindowSize(1<<20))
+	}
+	return nil, fmt.Errorf("unknown encoding %q", encoding)
+}
+
+// newDecoder decompresses r, which is encoded with encoding.
+func newDecoder(encoding string, r io.Reader) (io.ReadCloser, error) {
+	switch encoding {
+	case "gzip":
+		return gzip.NewReader(r)
+	case "br":
+		return ioutil.NopCloser(brotli.NewReader(r)), nil
+	case "zstd":
+		// bound the window a frame can ask for, decoded size is limited by
+		// whoever reads it
+		d, err := zstd.NewReader(r, zstd.WithDecoderConcurrency(1), zstd.WithDecoderMaxMemory(2*maxPasteSize))
+		if err != nil {
+			return nil, err
+		}
+		return d.IOReadCloser(), nil
+	}
+	return nil, fmt.Errorf("unknown encoding %q", encoding)
+}
+
+// decodePaste undoes the encoding a paste is stored with.
+func decodePaste(b []byte, encoding string) ([]byte, error) {
+	if encoding == "" {
+		return b, nil
+	}
+	r, err := newDecoder(encoding, bytes.NewReader(b))
+	if err != nil {
+		return nil, err
+	}
+	defer r.Close()
+	return ioutil.ReadAll(r)
+}
+
+// decodeBody undoes the Content-Encoding of an upload, so the paste is
+// stored decompressed and the size limits apply to what it expands to.
+func decodeBody(req *http.Request) error {
+	encoding := req.Header.Get("Content-Encoding")
+	if encoding == "" || encoding == "identity" {
+		return nil
+	}
+	known := false
+	for _, e := range responseEncodings {
+		known = known || e == encoding
+	}
+	if !known {
+		return unknownEncoding(encoding)
+	}
+	r, err := newDecoder(encoding, req.Body)
+	if err != nil {
+		return badUpload(fmt.Sprintf("can't decode %s body: %s", encoding, err.Error()))
+	}
+	req.Body = r
+	return nil
+}
+
+// compressible reports whether content of type ctype is worth compressing.
+func compressible(ctype string) bool {
+	mediaType, _, _ := mime.ParseMediaType(ctype)
+	return strings.HasPrefix(mediaType, "text/") || mediaType == "application/json"
+}
+
+// compressSealer compresses text pastes with zstd as they're stored, setting
+// *encoding when it does. Anything else is stored as is.
+func compressSealer(encoding *string) sealer {
+	return func(r io.Reader) io.Reader {
+		br := bufio.NewReader(r)
+		// DetectContentType only looks at the first 512 bytes
+		head, err := br.Peek(minCompressSize)
+		if err != nil && err != io.EOF {
+			return errReader{err}
+		}
+		if len(head) < minCompressSize || !compressible(http.DetectContentType(head)) {
+			return br
+		}
+		*encoding = "zstd"
+		return pipeSeal(br, func(w io.Writer, r io.Reader) error {
+			enc, err := zstd.NewWriter(w, zstd.WithEncoderLevel(zstd.SpeedBetterCompression), zstd.WithEncoderConcurrency(1))
+			if err != nil {
+				return err
+			}
+			if _, err := io.Copy(enc, r); err != nil {
+				enc.Close()
+				return err
+			}
+			return enc.Close()
+		})
+	}
+}
+
+// acceptsEncoding reports whether the client's Accept-Encoding allows
+// encoding.
+func acceptsEncoding(req *http.Request, encoding string) bool {
+	for _, part := range strings.Split(req.Header.Get("Accept-Encoding"), ",") {
+		params := strings.Split(part, ";")
+		if strings.TrimSpace(params[0]) != encoding {
+			continue
+		}
+		q := 1.0
+		for _, p := range params[1:] {
+			if p = strings.TrimSpace(p); strings.HasPrefix(p, "q=") {
+				q, _ = strconv.ParseFloat(p[len("q="):], 64)
+			}
+		}
+		return q > 0
+	}
+	return false
+}
+
+// negotiateEncoding picks the encoding to compress a response with, empty
+// when the client takes none of them.
+func negotiateEncoding(req *http.Request) string {
+	for _, encoding := range responseEncodings {
+		if acceptsEncoding(req, encoding) {
+			return encoding
+		}
+	}
+	return ""
+}
+
+// encodingWriter compresses a response on its way out, if it's a whole 200.
+// Anything else, like a 206 or 304, is passed through as is.
+type encodingWriter struct {
+	http.ResponseWriter
+	encoding    string
+	enc         io.WriteCloser
+	wroteHeader bool
+}
+
+func (e *encodingWriter) WriteHeader(code int) {
+	if !e.wroteHeader {
+		e.wroteHeader = true
+		if code == http.StatusOK {
+			enc, err := newEncoder(e.encoding, e.ResponseWriter)
+			if err == nil {
+				h := e.Header()
+				h.Del("Content-Length")
+				h.Set("Content-Encoding", e.encoding)
+				// same content, different bytes
+				if etag := h.Get("ETag"); etag != "" && !strings.HasPrefix(etag, "W/") {
+					h.Set("ETag", "W/"+etag)
+				}
+				e.enc = enc
+			}
+		}
+	}
+	e.ResponseWriter.WriteHeader(code)
+}
+
+func (e *encodingWriter) Write(b []byte) (int, error) {
+	if !e.wroteHeader {
+		e.WriteHeader(http.StatusOK)
+	}
+	if e.enc != nil {
+		return e.enc.Write(b)
+	}
+	return e.ResponseWriter.Write(b)
+}
+
+func (e *encodingWriter) Close() error {
+	if e.enc == nil {
+		return nil
+	}
+	return e.enc.Close()
+}
+
+// serveEncoded is http.ServeContent, compressed with the best encoding the
+// client accepts when the Content-Type already set is worth compressing.
+func serveEncoded(w http.ResponseWriter, req *http.Request, size int64, content io.ReadSeeker) {
+	w.Header().Add("Vary", "Accept-Encoding")
+	if encoding := negotiateEncoding(req); encoding != "" && size >= minCompressSize && compressible(w.Header().Get("Content-Type")) {
+		ew := &encodingWriter{ResponseWriter: w, encoding: encoding}
+		defer func() {
+			// HEAD responses have no body to finish
+			if err := ew.Close(); err != nil && !errors.Is(err, http.ErrBodyNotAllowed) {
+				log.Printf("[ERROR] %s (%s: %s)\n", req.URL.Path, encoding, err.Error())
+			}
+		}()
+		w = ew
+	}
+	http.ServeContent(w, req, "", time.Time{}, content)
+}
+
+// serveStored sends a paste stored with encoding as it is, to a client that
+// accepts that encoding.
+func (h *handler) serveStored(w http.ResponseWriter, req *http.Request, key string, info entry, encoding string) {
+	f := &pasteFile{s: h.store, key: key, size: info.Size}
+	defer f.Close()
+	setContentType(w, info.Name, func() []byte {
+		// sniff the decoded start of the paste
+		sf := &pasteFile{s: h.store, key: key, size: info.Size}
+		defer sf.Close()
+		r, err := newDecoder(encoding, sf)
+		if err != nil {
+			return nil
+		}
+		defer r.Close()
+		head := make([]byte, 512)
+		n, _ := io.ReadFull(r, head)
+		return head[:n]
+	})
+	w.Header().Add("Vary", "Accept-Encoding")
+	w.Header().Set("Content-Encoding", encoding)
+	w.Header().Set("ETag", fmt.Sprintf("W/%q", info.Hash))
+	http.ServeContent(w, req, "", time.Time{}, f)
+}
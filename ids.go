@@ -0,0 +1,198 @@
+package main
+
+import (
+	"encoding/json"
+	"errors"
+	"fmt"
+	"math"
+	"strings"
+	"sync"
+
+	"github.com/peterbourgon/diskv"
+)
+
+// idTries is how many random ids are tried at one length before a longer
+// one is used.
+const idTries = 8
+
+// slug length limits, short of a cid so the two can't be confused
+const (
+	minSlugLength = 3
+	maxSlugLength = 32
+)
+
+// reservedSlugs are route names, current and planned, that can't be claimed.
+var reservedSlugs = map[string]bool{
+	"s":      true,
+	"ipfs":   true,
+	"ipns":   true,
+	"diff":   true,
+	"debug":  true,
+	"static": true,
+	"api":    true,
+}
+
+// slugRecord is a claimed vanity slug. Owner is the sha256 of the deletion
+// token of the upload that claimed it, which can repoint it later.
+type slugRecord struct {
+	Hash  string
+	Owner string
+}
+
+// shortIDs persistently maps short url ids to the root cids of pastes, so a
+// paste can be shared as /aB3x instead of by its cid. The same cid always
+// gets the same id back.
+type shortIDs struct {
+	mu sync.Mutex // serialises picking new ids
+	d  *diskv.Diskv
+	n  int // ids handed out
+}
+
+func newShortIDs(dir string) *shortIDs {
+	ids := &shortIDs{d: diskv.New(diskv.Options{
+		BasePath:     dir,
+		CacheSizeMax: 1024 * 1024,
+		Transform: func(key string) []string {
+			return []string{key[len(key)-2:]}
+		},
+	})}
+	for key := range ids.d.Keys(nil) {
+		if !strings.HasPrefix(key, "cid-") && !strings.HasPrefix(key, "slug-") {
+			ids.n++
+		}
+	}
+	return ids
+}
+
+// reverseKey is where the id given to hash is kept.
+func reverseKey(hash string) string {
+	return "cid-" + hash
+}
+
+// length is the id length to start picking at. It grows so the index stays
+// under 1/16th full, which keeps random picks from colliding often.
+func (ids *shortIDs) length() int {
+	l := urlLength
+	for float64(ids.n) > math.Pow(float64(len(urlCharset)), float64(l))/16 {
+		l++
+	}
+	return l
+}
+
+// get resolves a short id or slug to the cid it names.
+func (ids *shortIDs) get(id string) (string, bool) {
+	if validShortID(id) {
+		if b, err := ids.d.Read(id); err == nil {
+			return string(b), true
+		}
+	}
+	if rec, ok := ids.slug(id); ok {
+		return rec.Hash, true
+	}
+	return "", false
+}
+
+// isSlug reports whether id resolves through a slug, which its owner can
+// point elsewhere, rather than a short id, which never changes.
+func (ids *shortIDs) isSlug(id string) bool {
+	if validShortID(id) && ids.d.Has(id) {
+		return false
+	}
+	_, ok := ids.slug(id)
+	return ok
+}
+
+func (ids *shortIDs) slug(slug string) (rec slugRecord, ok bool) {
+	if validSlug(slug) != nil {
+		return rec, false
+	}
+	b, err := ids.d.Read("slug-" + slug)
+	if err != nil {
+		return rec, false
+	}
+	return rec, json.Unmarshal(b, &rec) == nil
+}
+
+// canClaim reports whether slug is free, or owned by token.
+func (ids *shortIDs) canClaim(slug, token string) bool {
+	if ids.d.Has(slug) {
+		return false
+	}
+	rec, ok := ids.slug(slug)
+	return !ok || tokenMatches(rec.Owner, token)
+}
+
+// claim points slug at hash. A free slug becomes owned by owner, a claimed
+// one can only be repointed by presenting its owner's token.
+func (ids *shortIDs) claim(slug, hash, owner, token string) error {
+	ids.mu.Lock()
+	defer ids.mu.Unlock()
+
+	if !ids.canClaim(slug, token) {
+		return slugTaken{}
+	}
+	rec, ok := ids.slug(slug)
+	if !ok {
+		rec.Owner = sha256Hex([]byte(owner))
+	}
+	rec.Hash = hash
+	b, err := json.Marshal(rec)
+	if err != nil {
+		return err
+	}
+	return ids.d.Write("slug-"+slug, b)
+}
+
+// add returns the short id for hash, picking a new one if it has none.
+func (ids *shortIDs) add(hash string) (string, error) {
+	ids.mu.Lock()
+	defer ids.mu.Unlock()
+
+	if b, err := ids.d.Read(reverseKey(hash)); err == nil {
+		return string(b), nil
+	}
+	for l := ids.length(); l <= 2*urlLength+4; l++ {
+		for i := 0; i < idTries; i++ {
+			id := newID(l)
+			// a short id can't shadow a route, like /diff
+			if reservedSlugs[strings.ToLower(id)] || ids.d.Has(id) || ids.d.Has("slug-"+id) {
+				continue
+			}
+			if err := ids.d.Write(id, []byte(hash)); err != nil {
+				return "", err
+			}
+			ids.n++
+			return id, ids.d.Write(reverseKey(hash), []byte(id))
+		}
+	}
+	return "", errors.New("no free short id")
+}
+
+// validShortID keeps user supplied ids from escaping the id dir.
+func validShortID(id string) bool {
+	if len(id) < 2 {
+		return false
+	}
+	for _, c := range id {
+		if !strings.ContainsRune(urlCharset, c) {
+			return false
+		}
+	}
+	return true
+}
+
+// validSlug checks a requested slug, saying what's wrong with it.
+func validSlug(slug string) error {
+	if len(slug) < minSlugLength || len(slug) > maxSlugLength {
+		return badUpload(fmt.Sprintf("slugs are %d to %d characters", minSlugLength, maxSlugLength))
+	}
+	for _, c := range slug {
+		if !strings.ContainsRune(urlCharset+"-_", c) {
+			return badUpload("slugs can only use letters, digits, - and _")
+		}
+	}
+	if reservedSlugs[strings.ToLower(slug)] {
+		return badUpload(fmt.Sprintf("slug %q is reserved", slug))
+	}
+	return nil
+}
@@ -0,0 +1,267 @@
+package main
+
+import (
+	"crypto/rand"
+	"crypto/subtle"
+	"encoding/hex"
+	"encoding/json"
+	"errors"
+	"log"
+	"strconv"
+	"strings"
+	"sync"
+	"sync/atomic"
+	"time"
+
+	"github.com/peterbourgon/diskv"
+)
+
+// pasteMeta is the server side state kept for a paste, keyed by its root cid.
+type pasteMeta struct {
+	Created      time.Time
+	Expires      time.Time // zero never expires
+	MaxViews     int       // zero is unlimited
+	Views        int
+	DeleteTokens []string // sha256 of each uploader's deletion token
+	Removed      bool     // unpinned from the store
+	Deleted      bool     // taken down with a deletion token
+	Encrypted    bool     // stored sealed with a key only its url carries
+
+	// encrypted in the browser, the server never had the key
+	ClientEncrypted bool
+	// argon2 salt of the key a password protected paste is stored under
+	PasswordSalt string
+	// encrypted with age to the recipients of a ?to= upload
+	AgeEncrypted bool
+	// content coding the paste is stored with, eg. zstd, empty for none
+	Encoding string
+}
+
+// gone reports whether the paste should no longer be served.
+func (m pasteMeta) gone(now time.Time) bool {
+	return m.Removed ||
+		(!m.Expires.IsZero() && now.After(m.Expires)) ||
+		(m.MaxViews > 0 && m.Views >= m.MaxViews)
+}
+
+// sealed reports whether the paste is stored encrypted, so nothing inside it
+// can be looked at without decrypting all of it.
+func (m pasteMeta) sealed() bool {
+	return m.Encrypted || m.PasswordSalt != "" || m.AgeEncrypted || m.ClientEncrypted
+}
+
+// add records an upload of the paste. Identical uploads share a cid, so a
+// live paste keeps the longest lifetime anyone asked for, and the views
+// each upload allows add up. A view limit can't be put on a paste that is
+// already up without one, the upload is refused with viewsConflict rather
+// than quietly going unlimited.
+func (m *pasteMeta) add(now time.Time, lifetime time.Duration, maxViews int, exists bool) error {
+	var expires time.Time
+	if lifetime > 0 {
+		expires = now.Add(lifetime)
+	}
+	if !exists || m.gone(now) {
+		*m = pasteMeta{Created: now, Expires: expires, MaxViews: maxViews}
+		return nil
+	}
+	if maxViews > 0 && m.MaxViews == 0 {
+		return viewsConflict{}
+	}
+	if !m.Expires.IsZero() && (expires.IsZero() || expires.After(m.Expires)) {
+		m.Expires = expires
+	}
+	if m.MaxViews > 0 {
+		if maxViews == 0 {
+			m.MaxViews = 0
+		} else {
+			m.MaxViews += maxViews
+		}
+	}
+	return nil
+}
+
+// removeDeleteToken drops the uploader holding token, reporting whether it
+// was one of them.
+func (m *pasteMeta) removeDeleteToken(token string) bool {
+	for i, t := range m.DeleteTokens {
+		if tokenMatches(t, token) {
+			m.DeleteTokens = append(m.DeleteTokens[:i], m.DeleteTokens[i+1:]...)
+			return true
+		}
+	}
+	return false
+}
+
+// tokenMatches checks a presented token against a stored sha256 of one, in
+// constant time.
+func tokenMatches(sum, token string) bool {
+	return token != "" && subtle.ConstantTimeCompare([]byte(sum), []byte(sha256Hex([]byte(token)))) == 1
+}
+
+func newDeleteToken() (string, error) {
+	b := make([]byte, 16)
+	if _, err := rand.Read(b); err != nil {
+		return "", err
+	}
+	return hex.EncodeToString(b), nil
+}
+
+// pasteIndex is the persistent paste metadata index.
+type pasteIndex struct {
+	mu       sync.Mutex // serialises read-modify-write updates
+	d        *diskv.Diskv
+	unpinned int32 // set when something was unpinned since the last GC
+}
+
+func newPasteIndex(dir string) *pasteIndex {
+	return &pasteIndex{d: diskv.New(diskv.Options{
+		BasePath:     dir,
+		CacheSizeMax: 1024 * 1024,
+		Transform: func(key string) []string {
+			return []string{key[len(key)-2:]}
+		},
+	})}
+}
+
+// validIndexKey keeps user supplied keys from escaping the index dir.
+func validIndexKey(key string) bool {
+	if len(key) < 2 {
+		return false
+	}
+	for _, c := range key {
+		if !strings.ContainsRune(urlCharset+"-_", c) {
+			return false
+		}
+	}
+	return true
+}
+
+func (idx *pasteIndex) get(hash string) (m pasteMeta, ok bool) {
+	if !validIndexKey(hash) {
+		return m, false
+	}
+	b, err := idx.d.Read(hash)
+	if err != nil {
+		return m, false
+	}
+	return m, json.Unmarshal(b, &m) == nil
+}
+
+// update applies fn to the metadata for hash and saves the result, all under
+// the index lock. Unknown pastes get a zero pasteMeta and ok = false.
+func (idx *pasteIndex) update(hash string, fn func(m *pasteMeta, ok bool) error) error {
+	if !validIndexKey(hash) {
+		return errors.New("invalid paste key")
+	}
+	idx.mu.Lock()
+	defer idx.mu.Unlock()
+
+	m, ok := idx.get(hash)
+	if err := fn(&m, ok); err != nil {
+		return err
+	}
+	b, err := json.Marshal(m)
+	if err != nil {
+		return err
+	}
+	return idx.d.Write(hash, b)
+}
+
+// unpin releases the paste from the store and marks it removed. It's meant
+// to be called from inside update.
+func (idx *pasteIndex) unpin(s store, hash string, m *pasteMeta) {
+	if err := s.Unpin(hash); err != nil {
+		log.Printf("[ERROR] %s (unpin: %s)\n", hash, err.Error())
+	}
+	m.Removed = true
+	atomic.StoreInt32(&idx.unpinned, 1)
+}
+
+// remove unpins a paste once it's done being served, eg. after its last view.
+func (idx *pasteIndex) remove(s store, hash string) {
+	err := idx.update(hash, func(m *pasteMeta, ok bool) error {
+		if ok && !m.Removed {
+			idx.unpin(s, hash, m)
+		}
+		return nil
+	})
+	if err != nil {
+		log.Printf("[ERROR] %s (remove: %s)\n", hash, err.Error())
+	}
+}
+
+func (idx *pasteIndex) keys() (keys []string) {
+	for key := range idx.d.Keys(nil) {
+		keys = append(keys, key)
+	}
+	return
+}
+
+// parseExpiry reads a lifetime such as "90m", "12h" or "7d".
+func parseExpiry(s string) (d time.Duration, err error) {
+	if strings.HasSuffix(s, "d") {
+		var days int
+		days, err = strconv.Atoi(strings.TrimSuffix(s, "d"))
+		d = time.Duration(days) * 24 * time.Hour
+	} else {
+		d, err = time.ParseDuration(s)
+	}
+	if err == nil && d <= 0 {
+		err = errors.New("expiry must be positive")
+	}
+	return
+}
+
+// pasteExpiry picks the lifetime of a paste from the requested one, the
+// default and the limit for its size. Zero means forever.
+func pasteExpiry(requested time.Duration, size int64) time.Duration {
+	if requested == 0 {
+		requested = defaultExpiry
+	}
+	for _, l := range expiryLimits {
+		if size > l.size {
+			continue
+		}
+		if l.lifetime > 0 && (requested == 0 || requested > l.lifetime) {
+			return l.lifetime
+		}
+		break
+	}
+	return requested
+}
+
+// reap unpins every paste that expired or ran out of views, then garbage
+// collects the store if anything was unpinned since the last run.
+func reap(s store, idx *pasteIndex) {
+	now := time.Now()
+	removed := 0
+	for _, hash := range idx.keys() {
+		err := idx.update(hash, func(m *pasteMeta, ok bool) error {
+			if !ok || m.Removed || !m.gone(now) {
+				return nil
+			}
+			idx.unpin(s, hash, m)
+			removed++
+			return nil
+		})
+		if err != nil {
+			log.Printf("[ERROR] %s (reap: %s)\n", hash, err.Error())
+		}
+	}
+	if removed > 0 {
+		log.Printf("[REAP ] %d expired pastes\n", removed)
+	}
+
+	if atomic.SwapInt32(&idx.unpinned, 0) == 1 {
+		if err := s.GC(); err != nil {
+			log.Printf("[ERROR] gc (%s)\n", err.Error())
+		}
+	}
+}
+
+// reaper runs reap every interval, forever.
+func reaper(s store, idx *pasteIndex, interval time.Duration) {
+	for range time.Tick(interval) {
+		reap(s, idx)
+	}
+}
@@ -0,0 +1,117 @@
+package main
+
+import (
+	"encoding/json"
+	"fmt"
+	"html/template"
+	"log"
+	"net/http"
+	"net/url"
+	"path"
+	"strings"
+)
+
+const dirListingHTML = `<!doctype html>
+<html>
+<head>
+  <title>{{.Key}}</title>
+  <style>
+    body { background-color: #000000; color: #fff; font-family: monospace; }
+    a { color: #8ab4f8; }
+    td { padding: 2px 16px 2px 0; }
+    td.size { text-align: right; }
+  </style>
+</head>
+<body>
+  <h3>/{{.Key}}</h3>
+  <table>
+    {{if .Parent}}<tr><td><a href="{{.Parent}}">../</a></td><td></td></tr>{{end}}
+    {{range .Entries}}<tr><td><a href="{{.URL}}">{{.Name}}{{if .IsDir}}/{{end}}</a></td><td class="size">{{.Size}}</td></tr>
+    {{end}}
+  </table>
+</body>
+</html>
+`
+
+var dirListingTmpl = template.Must(template.New("listing").Parse(dirListingHTML))
+
+// listEntry is an entry of a directory listing, with a link to it.
+type listEntry struct {
+	entry
+	URL string `json:"url"`
+}
+
+// escapePath url escapes each segment of a slash separated key.
+func escapePath(key string) string {
+	parts := strings.Split(key, "/")
+	for i, p := range parts {
+		parts[i] = url.PathEscape(p)
+	}
+	return "/" + strings.Join(parts, "/")
+}
+
+// wantsHTML reports whether the client is a browser rather than curl & co.
+func wantsHTML(req *http.Request) bool {
+	return strings.Contains(req.Header.Get("Accept"), "text/html")
+}
+
+// list renders the directory at key.
+func (h *handler) list(w http.ResponseWriter, req *http.Request, key string, info entry) {
+	entries, err := h.store.Ls(key)
+	if err != nil {
+		http.Error(w, err.Error(), http.StatusInternalServerError)
+		log.Printf("[ERROR] %s (ls: %s)\n", key, err.Error())
+		return
+	}
+	writeListing(w, req, key, info, entries)
+}
+
+// writeListing renders entries found under key as html for browsers, json
+// on request (?json or Accept: application/json) and plain text otherwise.
+// Entry names may be paths, eg. the members of an archive.
+func writeListing(w http.ResponseWriter, req *http.Request, key string, info entry, entries []entry) {
+	key = strings.TrimSuffix(key, "/")
+	list := make([]listEntry, len(entries))
+	for i, e := range entries {
+		list[i] = listEntry{e, escapePath(key + "/" + e.Name)}
+		if e.IsDir {
+			list[i].URL += "/"
+		}
+	}
+
+	switch {
+	case wantsJSON(req):
+		w.Header().Set("Content-Type", "application/json")
+		json.NewEncoder(w).Encode(struct {
+			entry
+			Entries []listEntry `json:"entries"`
+		}{info, list})
+	case wantsHTML(req):
+		var parent string
+		if strings.Contains(key, "/") {
+			parent = escapePath(path.Dir(key)) + "/"
+		}
+		w.Header().Set("Content-Type", "text/html; charset=utf-8")
+		err := dirListingTmpl.Execute(w, struct {
+			Key     string
+			Parent  string
+			Entries []listEntry
+		}{key, parent, list})
+		if err != nil {
+			log.Printf("[ERROR] %s (listing: %s)\n", key, err.Error())
+		}
+	default:
+		w.Header().Set("Content-Type", "text/plain; charset=utf-8")
+		scheme := "http://"
+		if req.TLS != nil {
+			scheme = "https://"
+		}
+		for _, e := range list {
+			name := e.Name
+			if e.IsDir {
+				name += "/"
+			}
+			fmt.Fprintf(w, "%-32s %10d  %s%s%s\n", name, e.Size, scheme, req.Host, e.URL)
+		}
+	}
+}
@@ -0,0 +1,138 @@
+package main
+
+import (
+	"encoding/json"
+	"errors"
+	"fmt"
+	"net/http"
+	"sync"
+	"time"
+
+	"github.com/peterbourgon/diskv"
+)
+
+// nameRecord is a mutable paste published under a server managed ipns key.
+type nameRecord struct {
+	Key     string         // name of the key in the store's keystore
+	Hash    string         // cid the name currently points at
+	Token   string         // sha256 of the update token
+	Updated time.Time      // when Hash was last published
+	History []nameRevision // every cid published, oldest first
+}
+
+// nameRevision is one published version of a mutable paste.
+type nameRevision struct {
+	Hash      string    `json:"hash"`
+	Published time.Time `json:"published"`
+}
+
+// nameIndex keeps the mutable names the server publishes, so reads can
+// resolve them without waiting on the ipns network.
+type nameIndex struct {
+	mu sync.Mutex // serialises read-modify-write updates
+	d  *diskv.Diskv
+}
+
+func newNameIndex(dir string) *nameIndex {
+	return &nameIndex{d: diskv.New(diskv.Options{
+		BasePath:     dir,
+		CacheSizeMax: 1024 * 1024,
+		Transform: func(key string) []string {
+			return []string{key[len(key)-2:]}
+		},
+	})}
+}
+
+func (n *nameIndex) get(name string) (rec nameRecord, ok bool) {
+	if !validIndexKey(name) {
+		return rec, false
+	}
+	b, err := n.d.Read(name)
+	if err != nil {
+		return rec, false
+	}
+	return rec, json.Unmarshal(b, &rec) == nil
+}
+
+// update applies fn to the record for name and saves the result, all under
+// the index lock. Unknown names get a zero nameRecord and ok = false.
+func (n *nameIndex) update(name string, fn func(rec *nameRecord, ok bool) error) error {
+	if !validIndexKey(name) {
+		return errors.New("invalid name")
+	}
+	n.mu.Lock()
+	defer n.mu.Unlock()
+
+	rec, ok := n.get(name)
+	if err := fn(&rec, ok); err != nil {
+		return err
+	}
+	b, err := json.Marshal(rec)
+	if err != nil {
+		return err
+	}
+	return n.d.Write(name, b)
+}
+
+// publish points a mutable name at hash. With no name a new key and name are
+// made, and the update token needed to repoint it later is returned.
+// Otherwise token must be the name's update token.
+func (n *nameIndex) publish(s store, name, hash, token string) (string, string, error) {
+	var key string
+	if name == "" {
+		var err error
+		if token, err = newDeleteToken(); err != nil {
+			return "", "", err
+		}
+		key = "upld-" + newID(16)
+		if name, err = s.NewName(key); err != nil {
+			return "", "", err
+		}
+	}
+
+	err := n.update(name, func(rec *nameRecord, ok bool) error {
+		if key != "" {
+			*rec = nameRecord{Key: key, Token: sha256Hex([]byte(token))}
+		} else if !ok || !tokenMatches(rec.Token, token) {
+			return updateDenied{}
+		}
+		if err := s.Publish(rec.Key, hash); err != nil {
+			return err
+		}
+		rec.Hash = hash
+		rec.Updated = time.Now()
+		rec.History = append(rec.History, nameRevision{hash, rec.Updated})
+		return nil
+	})
+	if key == "" {
+		token = ""
+	}
+	return name, token, err
+}
+
+// history lists the revisions of a mutable paste, newest first, as json on
+// request and plain text otherwise.
+func (h *handler) history(w http.ResponseWriter, req *http.Request, name string) {
+	rec, _ := h.names.get(name)
+	revs := make([]nameRevision, len(rec.History))
+	for i, r := range rec.History {
+		revs[len(revs)-1-i] = r
+	}
+
+	if wantsJSON(req) {
+		w.Header().Set("Content-Type", "application/json")
+		json.NewEncoder(w).Encode(struct {
+			Name      string         `json:"name"`
+			Revisions []nameRevision `json:"revisions"`
+		}{name, revs})
+		return
+	}
+	w.Header().Set("Content-Type", "text/plain; charset=utf-8")
+	scheme := "http://"
+	if req.TLS != nil {
+		scheme = "https://"
+	}
+	for _, r := range revs {
+		fmt.Fprintf(w, "%s  %s%s/%s\n", r.Published.UTC().Format(time.RFC3339), scheme, req.Host, r.Hash)
+	}
+}
@@ -0,0 +1,209 @@
+package main
+
+import (
+	"crypto/cipher"
+	"crypto/rand"
+	"encoding/base64"
+	"html/template"
+	"log"
+	"net"
+	"net/http"
+	"runtime"
+	"strconv"
+	"sync"
+	"time"
+
+	"golang.org/x/crypto/argon2"
+)
+
+// argon2id parameters for password keys, the ones x/crypto/argon2 suggests
+// from the RFC 9106 drafts. The RFC's own 64 MiB option takes 3 passes, one
+// is kept since every read pays for it and stored pastes are keyed with it.
+const (
+	argonTime    = 1
+	argonMemory  = 64 * 1024 // KiB
+	argonThreads = 4
+)
+
+const passwordPageHTML = `<!doctype html>
+<html>
+<head>
+  <title>{{.Key}}</title>
+  <style>
+    body { background-color: #000000; color: #fff; font-family: monospace; }
+    input, button { background-color: #484848; color: #fff; border-width: 0; padding: 4px; }
+    .error { color: #f88; }
+  </style>
+</head>
+<body>
+  <h3>/{{.Key}} is password protected</h3>
+  {{if .Error}}<p class="error">{{.Error}}</p>{{end}}
+  <form method="POST" accept-charset="UTF-8">
+    <input type="password" name="password" autofocus/> <button type="submit">unlock</button>
+  </form>
+</body>
+</html>
+`
+
+var passwordPageTmpl = template.Must(template.New("password").Parse(passwordPageHTML))
+
+// argonSlots bounds the key derivations running at once, each one holds
+// argonMemory while it runs.
+var argonSlots = make(chan struct{}, runtime.NumCPU())
+
+// newPasswordAEAD makes the cipher for a new password protected paste, and
+// the salt that has to be kept to derive its key again.
+func newPasswordAEAD(password string) (cipher.AEAD, string, error) {
+	b := make([]byte, 16)
+	if _, err := rand.Read(b); err != nil {
+		return nil, "", err
+	}
+	salt := base64.RawStdEncoding.EncodeToString(b)
+	aead, err := passwordAEAD(password, salt)
+	return aead, salt, err
+}
+
+func passwordAEAD(password, salt string) (cipher.AEAD, error) {
+	b, err := base64.RawStdEncoding.DecodeString(salt)
+	if err != nil {
+		return nil, err
+	}
+	argonSlots <- struct{}{}
+	key := argon2.IDKey([]byte(password), b, argonTime, argonMemory, argonThreads, 32)
+	<-argonSlots
+	return keyAEAD(key)
+}
+
+// requestPassword is the password sent with a read, by curl -u or the
+// password prompt. It's never taken from the url, where it would be logged.
+func requestPassword(req *http.Request) string {
+	if _, password, ok := req.BasicAuth(); ok {
+		return password
+	}
+	return req.PostFormValue("password")
+}
+
+func clientIP(req *http.Request) string {
+	host, _, err := net.SplitHostPort(req.RemoteAddr)
+	if err != nil {
+		return req.RemoteAddr
+	}
+	return host
+}
+
+// unlock decrypts the password protected paste at key with the password
+// sent along. Without one, or with a wrong one, it asks for the password
+// and reports false.
+func (h *handler) unlock(w http.ResponseWriter, req *http.Request, hash, key string, meta pasteMeta) ([]byte, bool) {
+	password := requestPassword(req)
+	if password == "" {
+		askPassword(w, req, key, "")
+		return nil, false
+	}
+
+	// limited per paste, and per client across pastes so guessing at many
+	// pastes at once doesn't get around it
+	client, now := hash+" "+clientIP(req), time.Now()
+	wait := h.attempts.attempt(client, now)
+	if wait == 0 {
+		wait = h.clientAttempts.attempt(clientIP(req), now)
+	}
+	if wait > 0 {
+		w.Header().Set("Retry-After", strconv.Itoa(int(wait/time.Second)+1))
+		http.Error(w, "too many wrong passwords, try again later", http.StatusTooManyRequests)
+		log.Printf("[ERROR] %s (password attempts from %s)\n", key, clientIP(req))
+		return nil, false
+	}
+
+	sealed, err := readPaste(h.store, key)
+	if err != nil {
+		http.Error(w, err.Error(), http.StatusInternalServerError)
+		log.Printf("[ERROR] %s (%s)\n", key, err.Error())
+		return nil, false
+	}
+	aead, err := passwordAEAD(password, meta.PasswordSalt)
+	var plain []byte
+	if err == nil {
+		plain, err = decryptPaste(sealed, aead)
+	}
+	if err != nil {
+		askPassword(w, req, key, "wrong password")
+		log.Printf("[ERROR] %s (%s)\n", key, badPassword{}.Error())
+		return nil, false
+	}
+	h.attempts.forget(client)
+	return plain, true
+}
+
+// askPassword replies 401, with a prompt for browsers and a basic auth
+// challenge for curl -u.
+func askPassword(w http.ResponseWriter, req *http.Request, key, msg string) {
+	w.Header().Set("Cache-Control", "no-store")
+	if !wantsHTML(req) {
+		w.Header().Set("WWW-Authenticate", `Basic realm="password protected paste", charset="UTF-8"`)
+		http.Error(w, badPassword{}.Error(), http.StatusUnauthorized)
+		return
+	}
+	w.Header().Set("Content-Type", "text/html; charset=utf-8")
+	w.WriteHeader(http.StatusUnauthorized)
+	err := passwordPageTmpl.Execute(w, struct {
+		Key   string
+		Error string
+	}{key, msg})
+	if err != nil {
+		log.Printf("[ERROR] %s (password page: %s)\n", key, err.Error())
+	}
+}
+
+// attemptLimiter counts password attempts per client, so no one gets more
+// than max guesses per passwordLockout.
+type attemptLimiter struct {
+	mu       sync.Mutex
+	max      int
+	attempts map[string][]time.Time
+}
+
+func newAttemptLimiter(max int) *attemptLimiter {
+	return &attemptLimiter{max: max, attempts: make(map[string][]time.Time)}
+}
+
+// recent drops attempts by client older than passwordLockout. Callers hold
+// the lock.
+func (l *attemptLimiter) recent(client string, now time.Time) []time.Time {
+	times := l.attempts[client]
+	for len(times) > 0 && now.Sub(times[0]) >= passwordLockout {
+		times = times[1:]
+	}
+	if len(times) == 0 {
+		delete(l.attempts, client)
+	} else {
+		l.attempts[client] = times
+	}
+	return times
+}
+
+// attempt records a password attempt by client. Once it's out of attempts
+// nothing is recorded, and it gets how long to wait for the next one.
+func (l *attemptLimiter) attempt(client string, now time.Time) time.Duration {
+	l.mu.Lock()
+	defer l.mu.Unlock()
+
+	times := l.recent(client, now)
+	if len(times) >= l.max {
+		return passwordLockout - now.Sub(times[0])
+	}
+	l.attempts[client] = append(times, now)
+	if len(l.attempts) > maxPasswordClients {
+		for c := range l.attempts {
+			l.recent(c, now)
+		}
+	}
+	return 0
+}
+
+// forget clears the attempts of a client that got the password right.
+func (l *attemptLimiter) forget(client string) {
+	l.mu.Lock()
+	defer l.mu.Unlock()
+	delete(l.attempts, client)
+}
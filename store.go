@@ -0,0 +1,308 @@
+package main
+
+import (
+	"bytes"
+	"context"
+	"crypto/hmac"
+	"crypto/sha256"
+	"encoding/hex"
+	"encoding/xml"
+	"fmt"
+	"io"
+	"io/ioutil"
+	"net/http"
+	"net/url"
+	"os"
+	"path"
+	"strings"
+	"time"
+
+	shell "github.com/ipfs/go-ipfs-api"
+)
+
+// store is a content addressed paste backend. Keys are ipfs style paths,
+// either a bare "<cid>" or "<cid>/<name>" inside a directory.
+type store interface {
+	// Add stores a single file and returns its cid.
+	Add(r io.Reader) (string, error)
+	// AddDir stores a local directory tree and returns the cid of its root.
+	AddDir(dir string) (string, error)
+	// Stat describes the file or directory at key.
+	Stat(key string) (entry, error)
+	// Ls lists the entries of the directory at key.
+	Ls(key string) ([]entry, error)
+	// Cat opens length bytes of the file at key from offset. A negative
+	// length reads to the end of the file.
+	Cat(key string, offset, length int64) (io.ReadCloser, error)
+	// Unpin releases a root cid added with Add or AddDir so GC can free it.
+	Unpin(hash string) error
+	// GC frees everything that isn't pinned.
+	GC() error
+	// NewName makes a keypair named key for publishing a mutable name, and
+	// returns the name.
+	NewName(key string) (string, error)
+	// Publish points the name of key at hash.
+	Publish(key, hash string) error
+}
+
+// entry describes a file or directory in a store.
+type entry struct {
+	Name  string `json:"name"`
+	Hash  string `json:"hash"` // cid of the entry itself
+	Size  int64  `json:"size"` // file size, or cumulative size for directories
+	IsDir bool   `json:"dir"`
+}
+
+func newStore(backend, ipfsAddr, diskPath string, s3 s3Config) (store, error) {
+	switch backend {
+	case "ipfs":
+		return &ipfsStore{shell.NewShell(ipfsAddr)}, nil
+	case "mem":
+		return newMemStore(), nil
+	case "disk":
+		return &dagStore{blocks: &diskBlocks{diskPath}}, nil
+	case "s3":
+		if s3.Bucket == "" {
+			return nil, fmt.Errorf("s3 store needs a bucket")
+		}
+		return &dagStore{blocks: &s3Blocks{s3, &http.Client{Timeout: time.Minute}}}, nil
+	}
+	return nil, fmt.Errorf("unknown store backend %q", backend)
+}
+
+/* --- ipfs --- */
+
+// ipfsStore talks to a go-ipfs daemon over its http rpc api.
+type ipfsStore struct {
+	sh *shell.Shell
+}
+
+func (s *ipfsStore) Add(r io.Reader) (string, error) { return s.sh.Add(r) }
+
+func (s *ipfsStore) AddDir(dir string) (string, error) { return s.sh.AddDir(dir) }
+
+func (s *ipfsStore) Stat(key string) (entry, error) {
+	var st struct {
+		Hash           string
+		Size           int64
+		CumulativeSize int64
+		Type           string
+	}
+	if err := s.sh.Request("files/stat", "/ipfs/"+key).Exec(context.Background(), &st); err != nil {
+		return entry{}, err
+	}
+	e := entry{Name: path.Base(key), Hash: st.Hash, Size: st.Size, IsDir: st.Type == "directory"}
+	if e.IsDir {
+		e.Size = st.CumulativeSize
+	}
+	return e, nil
+}
+
+func (s *ipfsStore) Ls(key string) ([]entry, error) {
+	var out struct {
+		Objects []struct {
+			Links []struct {
+				Name string
+				Hash string
+				Size int64
+				Type int
+			}
+		}
+	}
+	if err := s.sh.Request("ls", key).Exec(context.Background(), &out); err != nil {
+		return nil, err
+	}
+	var entries []entry
+	for _, o := range out.Objects {
+		for _, l := range o.Links {
+			// unixfs type 1 is a directory
+			entries = append(entries, entry{Name: l.Name, Hash: l.Hash, Size: l.Size, IsDir: l.Type == 1})
+		}
+	}
+	return entries, nil
+}
+
+func (s *ipfsStore) Cat(key string, offset, length int64) (io.ReadCloser, error) {
+	req := s.sh.Request("cat", key).Option("offset", offset)
+	if length >= 0 {
+		req = req.Option("length", length)
+	}
+	resp, err := req.Send(context.Background())
+	if err != nil {
+		return nil, err
+	}
+	if resp.Error != nil {
+		resp.Close()
+		return nil, resp.Error
+	}
+	return resp.Output, nil
+}
+
+func (s *ipfsStore) Unpin(hash string) error { return s.sh.Unpin(hash) }
+
+func (s *ipfsStore) GC() error {
+	resp, err := s.sh.Request("repo/gc").Send(context.Background())
+	if err != nil {
+		return err
+	}
+	defer resp.Close()
+	if resp.Error != nil {
+		return resp.Error
+	}
+	// the daemon streams removed cids while it works, closing early cancels it
+	_, err = io.Copy(ioutil.Discard, resp.Output)
+	return err
+}
+
+func (s *ipfsStore) NewName(key string) (string, error) {
+	var out struct{ Name, Id string }
+	err := s.sh.Request("key/gen", key).Option("type", "ed25519").Exec(context.Background(), &out)
+	return out.Id, err
+}
+
+func (s *ipfsStore) Publish(key, hash string) error {
+	// allow-offline so a lone daemon can still publish, peers pick it up later
+	return s.sh.Request("name/publish", "/ipfs/"+hash).
+		Option("key", key).
+		Option("allow-offline", true).
+		Exec(context.Background(), nil)
+}
+
+/* --- s3 --- */
+
+type s3Config struct {
+	Endpoint  string // eg. https://s3.us-east-1.amazonaws.com or http://localhost:9000
+	Bucket    string
+	Region    string
+	AccessKey string
+	SecretKey string
+}
+
+// s3Blocks stores dag blocks as objects in an s3 compatible bucket, using
+// path style addressing so self hosted minio and friends work as is.
+type s3Blocks struct {
+	conf   s3Config
+	client *http.Client
+}
+
+func (b *s3Blocks) Get(key string) ([]byte, error) {
+	resp, err := b.do("GET", "blocks/"+key, nil, nil)
+	if err != nil {
+		return nil, err
+	}
+	defer resp.Body.Close()
+	if resp.StatusCode == http.StatusNotFound {
+		return nil, os.ErrNotExist
+	} else if resp.StatusCode != http.StatusOK {
+		return nil, fmt.Errorf("s3 get %s: %s", key, resp.Status)
+	}
+	return ioutil.ReadAll(resp.Body)
+}
+
+func (b *s3Blocks) Put(key string, data []byte) error {
+	resp, err := b.do("PUT", "blocks/"+key, nil, data)
+	if err != nil {
+		return err
+	}
+	resp.Body.Close()
+	if resp.StatusCode != http.StatusOK {
+		return fmt.Errorf("s3 put %s: %s", key, resp.Status)
+	}
+	return nil
+}
+
+func (b *s3Blocks) Delete(key string) error {
+	resp, err := b.do("DELETE", "blocks/"+key, nil, nil)
+	if err != nil {
+		return err
+	}
+	resp.Body.Close()
+	if resp.StatusCode != http.StatusNoContent && resp.StatusCode != http.StatusOK {
+		return fmt.Errorf("s3 delete %s: %s", key, resp.Status)
+	}
+	return nil
+}
+
+func (b *s3Blocks) Keys() (keys []string, err error) {
+	query := url.Values{"list-type": {"2"}, "prefix": {"blocks/"}}
+	for {
+		resp, err := b.do("GET", "", query, nil)
+		if err != nil {
+			return nil, err
+		}
+		var list struct {
+			Contents              []struct{ Key string }
+			IsTruncated           bool
+			NextContinuationToken string
+		}
+		if resp.StatusCode == http.StatusOK {
+			err = xml.NewDecoder(resp.Body).Decode(&list)
+		} else {
+			err = fmt.Errorf("s3 list: %s", resp.Status)
+		}
+		resp.Body.Close()
+		if err != nil {
+			return nil, err
+		}
+
+		for _, c := range list.Contents {
+			keys = append(keys, strings.TrimPrefix(c.Key, "blocks/"))
+		}
+		if !list.IsTruncated {
+			return keys, nil
+		}
+		query.Set("continuation-token", list.NextContinuationToken)
+	}
+}
+
+// do sends an AWS SigV4 signed request for object (relative to the bucket).
+func (b *s3Blocks) do(method, object string, query url.Values, body []byte) (*http.Response, error) {
+	u := fmt.Sprintf("%s/%s/%s", strings.TrimRight(b.conf.Endpoint, "/"), b.conf.Bucket, object)
+	req, err := http.NewRequest(method, u, bytes.NewReader(body))
+	if err != nil {
+		return nil, err
+	}
+	// sigv4 wants %20 for spaces and the parameters sorted, as Encode does
+	req.URL.RawQuery = strings.ReplaceAll(query.Encode(), "+", "%20")
+
+	now := time.Now().UTC()
+	amzDate := now.Format("20060102T150405Z")
+	scope := fmt.Sprintf("%s/%s/s3/aws4_request", now.Format("20060102"), b.conf.Region)
+	payloadHash := sha256Hex(body)
+	req.Header.Set("x-amz-date", amzDate)
+	req.Header.Set("x-amz-content-sha256", payloadHash)
+
+	signedHeaders := "host;x-amz-content-sha256;x-amz-date"
+	canonicalRequest := strings.Join([]string{
+		method,
+		req.URL.EscapedPath(),
+		req.URL.RawQuery,
+		"host:" + req.URL.Host,
+		"x-amz-content-sha256:" + payloadHash,
+		"x-amz-date:" + amzDate,
+		"",
+		signedHeaders,
+		payloadHash,
+	}, "\n")
+	stringToSign := strings.Join([]string{"AWS4-HMAC-SHA256", amzDate, scope, sha256Hex([]byte(canonicalRequest))}, "\n")
+
+	k := hmacSHA256([]byte("AWS4"+b.conf.SecretKey), now.Format("20060102"))
+	k = hmacSHA256(k, b.conf.Region)
+	k = hmacSHA256(k, "s3")
+	k = hmacSHA256(k, "aws4_request")
+	req.Header.Set("Authorization", fmt.Sprintf("AWS4-HMAC-SHA256 Credential=%s/%s, SignedHeaders=%s, Signature=%s",
+		b.conf.AccessKey, scope, signedHeaders, hex.EncodeToString(hmacSHA256(k, stringToSign))))
+
+	return b.client.Do(req)
+}
+
+func sha256Hex(data []byte) string {
+	sum := sha256.Sum256(data)
+	return hex.EncodeToString(sum[:])
+}
+
+func hmacSHA256(key []byte, data string) []byte {
+	mac := hmac.New(sha256.New, key)
+	mac.Write([]byte(data))
+	return mac.Sum(nil)
+}
@@ -18,7 +18,15 @@
 package main
 
 import (
+	"archive/tar"
+	"bufio"
 	"bytes"
+	"crypto/cipher"
+	"encoding/json"
+	"errors"
+	"expvar"
+	"flag"
+	"io"
 	"os"
 	"path"
 
@@ -27,11 +35,15 @@ import (
 	"io/ioutil"
 	"log"
 	"math/rand"
+	"mime"
+	"mime/multipart"
 	"net/http"
 	"os/exec"
+	"strconv"
 	"strings"
 	"time"
 
+	"filippo.io/age"
 	"github.com/gorilla/mux"
 	md "github.com/shurcooL/github_flavored_markdown"
 )
@@ -41,23 +53,61 @@ const (
 	/* --- url settings ---  */
 	formVal      = "p" // the value the upload form uses. ie; 'p=<-'
 	minPasteSize = 16
-	maxPasteSize = 1024 * 1024 * 1024                                               // 32 MB
-	urlLength    = 4                                                                // charlength of the url
+	maxPasteSize = 32 * 1024 * 1024                                                 // 32 MB
+	urlLength    = 4                                                                // starting charlength of short urls, grows as ids fill up
 	urlCharset   = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789" // available characters the url can use
 
+	/* --- archive settings --- */
+	maxArchiveFiles = 4096 // most entries an uploaded archive may hold
+
+	/* --- compression settings --- */
+	compressText    = false // zstd compress text pastes before storing them (-zstd)
+	minCompressSize = 1024  // smallest paste worth compressing, at rest or on the wire
+
+	/* --- password settings --- */
+	maxPasswordAttempts = 5                // password guesses a client gets at a paste per lockout
+	maxClientAttempts   = 50               // password guesses a client gets across all pastes per lockout
+	passwordLockout     = 15 * time.Minute // how long attempts are remembered
+	maxPasswordClients  = 100000           // attempts tracked before old ones are swept
+
 	/* --- database settings --- */
-	basePath  = "pastes"          // base paste storage dir
-	cacheSize = 128 * 1024 * 1024 // 128 MB
+	basePath     = "pastes"          // base paste storage dir
+	cachePath    = "cache"           // default read cache dir, empty disables it (-cache-dir)
+	cacheSize    = 128 * 1024 * 1024 // 128 MB
+	memCacheSize = 16 * 1024 * 1024  // 16 MB of the read cache kept in memory
+	storeType    = "ipfs"            // default storage backend: ipfs, disk, s3 or mem (-store)
+	storePath    = "store"           // default block dir for the disk backend (-store-path)
+	ipfsAPI      = "localhost:5001"  // default ipfs daemon rpc api address (-ipfs-api)
+	s3Region     = "us-east-1"       // default s3 region (-s3-region)
+	indexPath    = "index"           // default paste metadata dir (-index-dir)
+	idPath       = "ids"             // default short url id dir (-id-dir)
+	namePath     = "names"           // default mutable ipns name dir (-name-dir)
+
+	/* --- expiry settings --- */
+	defaultExpiry = 0 * time.Hour    // lifetime of pastes that don't ask for one, 0 = forever
+	reapInterval  = 10 * time.Minute // how often expired pastes are unpinned
 
 	/* --- server settings --- */
-	useSSL      = true
-	httpsPort   = 8443                 // ssl port
-	sslCertPath = "cert/fullchain.cer" // ssl cert
-	sslKeyPath  = "cert/upld.info.key" // ssl priv key
-	httpPort    = 8080                 // http port
-	bindAddress = ""                   // bind address
+	useSSL       = true
+	httpsPort    = 8443                 // ssl port
+	sslCertPath  = "cert/fullchain.cer" // ssl cert
+	sslKeyPath   = "cert/upld.info.key" // ssl priv key
+	httpPort     = 8080                 // http port
+	bindAddress  = ""                   // bind address
+	adminAddress = ""                   // where /debug/vars is served, eg. localhost:6060, empty disables it (-admin-addr)
 )
 
+// maximum paste lifetime by size. the first limit the paste fits under
+// applies, a zero lifetime allows keeping it forever.
+var expiryLimits = []struct {
+	size     int64
+	lifetime time.Duration
+}{
+	{1024 * 1024, 0},                        // up to 1 MB
+	{8 * 1024 * 1024, 365 * 24 * time.Hour}, // up to 8 MB
+	{maxPasteSize, 30 * 24 * time.Hour},     // anything bigger
+}
+
 const htmlPrefix = `<!doctype html>
   <html>
   <head>
@@ -89,16 +139,25 @@ const htmlPrefix = `<!doctype html>
       }
       input {
         background-color: #484848;
-        width: 75%;
+        width: 65%;
         height: 3vh;
         display: inline-block;
       }
+      label {
+        width: 10%;
+        display: inline-block;
+        text-align: center;
+      }
+      input[type=checkbox] {
+        width: auto;
+        height: auto;
+      }
     </style>
   </head>
 <body>
   <form action="{{.Scheme}}://{{.BaseURL}}" spellcheck="false" method="POST" accept-charset="UTF-8">
     <div>
-      <input name="file" placeholder="(enter optional filename...)"/><button type="submit">paste to ipfs</button>
+      <input name="file" placeholder="(enter optional filename...)"/><label title="encrypt in the browser, the key stays in the url after the #"><input type="checkbox" name="zk"/> encrypt</label><button type="submit">paste to ipfs</button>
     </div>
     <textarea name="p">
 
@@ -120,13 +179,25 @@ const standardUsageText = `{{.BaseURL}}(1)                              UPLD.IS
      # Command output
      &lt;command&gt; | curl {{.BaseURL}}{{.SubDir}} -T -
 
+     # Several files as one directory
+     curl {{.BaseURL}} -F f=@&lt;file&gt; -F f=@&lt;file&gt;
+     tar c &lt;files&gt; | curl {{.BaseURL}} -H 'Content-Type: application/x-tar' -T -
+
+     # Unpack a .tar, .tar.gz or .zip into a directory
+     curl '{{.BaseURL}}/?extract' -T &lt;archive&gt;
+
+     # Compressed upload
+     gzip -c &lt;file&gt; | curl {{.BaseURL}} -H 'Content-Encoding: gzip' -T -
+
      # View help info
      curl {{.BaseURL}}
  
  DESCRIPTION
      A simple, no bullshit command line pastebin, that stores files on IPFS. Pastes are
-     created using HTTP PUT, or POST requests. A url is returned, but you can also view
-     the file with the ipfs hash/name.
+     created using HTTP PUT, or POST requests. A short url is returned, but you can also
+     view the file with the ipfs hash/name, sent in the X-Ipfs-Path header. Directories are listed, add ?json for json,
+     or download them whole with ?tar, ?tar.gz or ?zip. Archive pastes are listed
+     with ?ls, and their members served from /<hash>/<archive>/<path>.
  
  INSTALL
      Add this to your shell's .rc for an easy to use alias for uploading files. 
@@ -135,15 +206,48 @@ const standardUsageText = `{{.BaseURL}}(1)                              UPLD.IS
  
  EXAMPLE
      $ ps -aux | curl {{.BaseURL}} -T -
-       {{.Scheme}}://{{.BaseURL}}/<hash>
+       {{.Scheme}}://{{.BaseURL}}/<id>
      $ curl {{.BaseURL}} -T filename.png
-       {{.Scheme}}://{{.BaseURL}}/<hash>/filename.png
+       {{.Scheme}}://{{.BaseURL}}/<id>/filename.png
 
      # ALIAS
      $ upld filename.go
-       {{.Scheme}}://{{.BaseURL}}/<hash>/filename.go
+       {{.Scheme}}://{{.BaseURL}}/<id>/filename.go
      $ upld <<< ps -aux
-       {{.Scheme}}://{{.BaseURL}}/<hash>
+       {{.Scheme}}://{{.BaseURL}}/<id>
+
+ OPTIONS
+     Uploads take these as query parameters, or as the matching X- header.
+
+     ?expires=&lt;duration&gt;   delete the paste after eg. 30m, 12h or 7d
+     ?burn                 the paste can be read exactly once
+     ?views=&lt;n&gt;            the paste can be read n times
+     ?json                 reply with json, including the deletion token
+     ?slug=&lt;name&gt;          claim /&lt;name&gt; as the url, same as PUT /s/&lt;name&gt;
+     ?ipns                 publish under a mutable /ipns/&lt;name&gt; url, see UPDATE
+     ?encrypt              store the paste encrypted, the key is only in the reply url
+     -u :&lt;pw&gt;              store the paste encrypted under a password, or send X-Password
+     ?to=&lt;pubkey&gt;          encrypt with age to an age or ssh public key, repeatable
+
+ UPDATE
+     ?ipns uploads also get a secret X-Update-Token. PUT to the /ipns/ url with it
+     to point the name at new content.
+
+     $ curl -H 'X-Update-Token: &lt;token&gt;' {{.BaseURL}}/ipns/&lt;name&gt; -T &lt;file&gt;
+
+     Add '?history' to the /ipns/ url to list every version, and '?diff=&lt;hash&gt;' to
+     any paste url to see what changed since an older one.
+
+ DIFF
+     Compare any two pastes, add '?w' to ignore whitespace. Browsers get a side by
+     side view, or '?unified' for a highlighted unified diff.
+
+     $ curl {{.BaseURL}}/diff/&lt;id&gt;/&lt;id&gt;
+
+ DELETE
+     Every upload gets a secret deletion token in the X-Delete-Token header.
+
+     $ curl -X DELETE -H 'X-Delete-Token: &lt;token&gt;' {{.BaseURL}}/&lt;hash&gt;
 
  FILE VIEW
      Add '?md' to the paste url to parse a github flavored markdown file into an html 
@@ -158,15 +262,55 @@ const standardUsageText = `{{.BaseURL}}(1)                              UPLD.IS
 
 const htmlSuffix = `</textarea>
  </form>
+ <script>
+  // encrypted pastes are sealed here, only the ciphertext is posted and the
+  // key goes in the url fragment, which browsers never send
+  const form = document.querySelector("form");
+  form.addEventListener("submit", async (e) => {
+    if (!form.zk.checked) {
+      return;
+    }
+    e.preventDefault();
+    const key = await crypto.subtle.generateKey({name: "AES-GCM", length: 256}, true, ["encrypt"]);
+    const iv = crypto.getRandomValues(new Uint8Array(12));
+    const ct = await crypto.subtle.encrypt({name: "AES-GCM", iv: iv}, key, new TextEncoder().encode(form.p.value));
+    const raw = await crypto.subtle.exportKey("raw", key);
+    const body = new URLSearchParams({
+      p: JSON.stringify({v: 1, iv: b64(iv), ct: b64(ct)}),
+      file: form.file.value,
+    });
+    const resp = await fetch(form.action + "/?zk", {method: "POST", body: body});
+    const text = await resp.text();
+    if (!resp.ok) {
+      alert(text);
+      return;
+    }
+    location.href = text.trim() + "#" + b64(raw);
+  });
+  function b64(buf) {
+    let s = "";
+    new Uint8Array(buf).forEach((b) => s += String.fromCharCode(b));
+    return btoa(s).replaceAll("+", "-").replaceAll("/", "_").replaceAll("=", "");
+  }
+ </script>
  </body>
  </html>`
 
 // errors n shit
 type (
-	pasteTooLarge struct{}
-	pasteTooSmall struct{}
-	pasteNotFound struct{}
-	pygmentsError struct{}
+	pasteTooLarge   struct{}
+	pasteTooSmall   struct{}
+	pasteNotFound   struct{}
+	pasteGone       struct{}
+	pygmentsError   struct{}
+	deleteDenied    struct{}
+	slugTaken       struct{}
+	viewsConflict   struct{}
+	updateDenied    struct{}
+	decryptDenied   struct{}
+	badPassword     struct{}
+	badUpload       string
+	unknownEncoding string
 )
 
 func (e pasteTooLarge) Error() string {
@@ -174,81 +318,415 @@ func (e pasteTooLarge) Error() string {
 }
 func (e pasteTooSmall) Error() string { return "paste too small" }
 func (e pasteNotFound) Error() string { return "unknown ipfs hash, or not a file" }
+func (e pasteGone) Error() string     { return "paste expired, was deleted, or reached its view limit" }
+func (e deleteDenied) Error() string  { return "missing or invalid deletion token" }
+func (e slugTaken) Error() string     { return "slug is taken" }
+func (e viewsConflict) Error() string {
+	return "the same paste is already up without a view limit, it can't be made burn after reading or view limited"
+}
+func (e updateDenied) Error() string  { return "missing or invalid update token" }
+func (e decryptDenied) Error() string { return "missing or invalid paste key" }
+func (e badPassword) Error() string   { return "missing or wrong password" }
+func (e badUpload) Error() string     { return string(e) }
 func (e pygmentsError) Error() string {
 	return "unknown pygements lexar shortcode. view available lexars at https://pygments.org/docs/lexers/"
 }
+func (e unknownEncoding) Error() string {
+	return fmt.Sprintf("unsupported Content-Encoding %q, use %s", string(e), strings.Join(responseEncodings, ", "))
+}
 
-func newID() string {
-	urlID := make([]byte, urlLength)
+func newID(length int) string {
+	urlID := make([]byte, length)
 	for i := range urlID {
 		urlID[i] = urlCharset[rand.Intn(len(urlCharset))]
 	}
 	return string(urlID)
 }
 
-func readPaste(key string) (paste []byte, err error) {
-	// Unnamed file (use regular ipfs hash)
-	cmd := exec.Command("ipfs", "cat", key)
-	paste, err = cmd.Output()
+func readPaste(s store, key string) (paste []byte, err error) {
+	r, err := s.Cat(key, 0, -1)
+	if err != nil {
+		return nil, pasteNotFound{}
+	}
+	defer r.Close()
+
+	paste, err = ioutil.ReadAll(r)
 	if err != nil {
 		err = pasteNotFound{}
 	}
 	return
 }
 
-func writePaste(name string, data []byte) (key string, err error) {
-	if len(data) > maxPasteSize {
-		err = pasteTooLarge{}
-		return
-	} else if len(data) < minPasteSize {
-		err = pasteTooSmall{}
-		return
+// inlineType reports whether a browser can be left to show content of
+// mediaType as it is. Only types that can't run script on the site's origin
+// are on the list.
+func inlineType(mediaType string) bool {
+	switch {
+	case mediaType == "text/plain" || mediaType == "application/pdf":
+		return true
+	case mediaType == "image/svg+xml":
+		return false
 	}
+	return strings.HasPrefix(mediaType, "image/") ||
+		strings.HasPrefix(mediaType, "audio/") ||
+		strings.HasPrefix(mediaType, "video/")
+}
 
-	temp_dir := path.Join("pastes", newID())
-	if name != "" {
-		if err := os.MkdirAll(temp_dir, 0755); err != nil {
-			return "", err
+// textType reports whether content of mediaType is text that's better read
+// as plain text than downloaded, eg. html, xml or javascript.
+func textType(mediaType string) bool {
+	return strings.HasPrefix(mediaType, "text/") ||
+		strings.HasSuffix(mediaType, "+xml") ||
+		strings.HasSuffix(mediaType, "+json") ||
+		mediaType == "application/xml" ||
+		mediaType == "application/json" ||
+		mediaType == "application/javascript"
+}
+
+// setContentType sets the Content-Type of a paste named name, going by its
+// extension and falling back to sniffing the bytes peek returns. Only
+// inlineType types are served as they are, with text assumed to be utf-8.
+// Other text is served as text/plain, and anything else gets a
+// Content-Disposition so it's never opened inline.
+func setContentType(w http.ResponseWriter, name string, peek func() []byte) {
+	ctype := mime.TypeByExtension(path.Ext(name))
+	if ctype == "" {
+		ctype = http.DetectContentType(peek())
+	}
+	mediaType, params, err := mime.ParseMediaType(ctype)
+	switch {
+	case err == nil && inlineType(mediaType):
+		if strings.HasPrefix(mediaType, "text/") && params["charset"] == "" {
+			ctype = mediaType + "; charset=utf-8"
+		}
+	case err != nil || textType(mediaType):
+		ctype = "text/plain; charset=utf-8"
+	case name == "":
+		w.Header().Set("Content-Disposition", "attachment")
+	default:
+		w.Header().Set("Content-Disposition", mime.FormatMediaType("attachment", map[string]string{"filename": name}))
+	}
+	w.Header().Set("Content-Type", ctype)
+}
+
+// pasteFile is a lazily opened io.ReadSeeker over a stored paste, so ranges
+// can be served without reading the whole file.
+type pasteFile struct {
+	s      store
+	key    string
+	size   int64
+	offset int64
+	r      io.ReadCloser
+}
+
+func (f *pasteFile) Read(b []byte) (int, error) {
+	if f.offset >= f.size {
+		return 0, io.EOF
+	}
+	if f.r == nil {
+		r, err := f.s.Cat(f.key, f.offset, -1)
+		if err != nil {
+			return 0, err
 		}
+		f.r = r
 	}
+	n, err := f.r.Read(b)
+	f.offset += int64(n)
+	return n, err
+}
+
+func (f *pasteFile) Seek(offset int64, whence int) (int64, error) {
+	switch whence {
+	case io.SeekCurrent:
+		offset += f.offset
+	case io.SeekEnd:
+		offset += f.size
+	}
+	if offset < 0 {
+		return 0, errors.New("seek before start of paste")
+	}
+	if offset != f.offset && f.r != nil {
+		f.r.Close()
+		f.r = nil
+	}
+	f.offset = offset
+	return offset, nil
+}
 
-	temp_file := path.Join(temp_dir, name) // temp_dir = file if unnamed
-	f, err := os.Create(temp_file)
+// ReadAt lets zip archives be read in place, each call is a ranged Cat.
+func (f *pasteFile) ReadAt(b []byte, off int64) (int, error) {
+	if off >= f.size {
+		return 0, io.EOF
+	}
+	r, err := f.s.Cat(f.key, off, int64(len(b)))
 	if err != nil {
-		return "", err
+		return 0, err
+	}
+	defer r.Close()
+	n, err := io.ReadFull(r, b)
+	if err == io.ErrUnexpectedEOF {
+		err = io.EOF
 	}
+	return n, err
+}
 
-	f.Write(data)
-	f.Close()
+func (f *pasteFile) Close() error {
+	if f.r == nil {
+		return nil
+	}
+	return f.r.Close()
+}
+
+// pasteReader enforces maxPasteSize on an upload while it streams through.
+type pasteReader struct {
+	r        io.Reader
+	n        int64
+	tooLarge bool
+}
+
+func (p *pasteReader) Read(b []byte) (int, error) {
+	n, err := p.r.Read(b)
+	p.n += int64(n)
+	if p.n > maxPasteSize {
+		p.tooLarge = true
+		return n, pasteTooLarge{}
+	}
+	return n, err
+}
 
-	// Add to IPFS
+// check maps the error from whatever consumed the reader to pasteTooLarge
+// when it went over the limit, as backends may wrap the read error.
+func (p *pasteReader) check(err error) error {
+	if p.tooLarge {
+		return pasteTooLarge{}
+	}
+	return err
+}
+
+// writePaste stores a single paste, sealed on its way into the store. The
+// size limits apply to the paste as uploaded, not to what sealing makes of it.
+func writePaste(s store, name string, r io.Reader, seal sealing) (key string, size int64, err error) {
 	if name != "" {
 		// Named file (use a dir to preserve filename)
-		cmd := exec.Command("ipfs", "add", "-r", temp_dir)
-		output, err := cmd.Output()
+		if !validFileName(name) {
+			return "", 0, badUpload("invalid file name")
+		}
+		var hash string
+		hash, size, err = writeDir(s, r, func(dir string, r io.Reader) error {
+			// archives, listings and ipfs serve files in a directory as
+			// they're stored, so they're never compressed
+			sealed := sealing{encrypt: seal.encrypt}.seal(r)
+			defer closeSealed(sealed)
+			return saveFile(dir, name, sealed)
+		})
 		if err != nil {
-			return "", err
+			return "", size, err
 		}
+		return fmt.Sprintf("%s/%s", hash, name), size, nil
+	}
 
-		// Create a File URL and return
-		lines := strings.Split(string(output[:]), "\n")
-		words := strings.Split(lines[len(lines)-2], " ")
-		key = fmt.Sprintf("%s/%s", words[1], name)
-	} else {
-		// Unnamed file (use regular ipfs hash)
-		cmd := exec.Command("ipfs", "add", temp_file)
-		output, err := cmd.Output()
+	// Unnamed file (use regular ipfs hash)
+	body := &pasteReader{r: r}
+	sealed := seal.seal(body)
+	defer closeSealed(sealed)
+	data := bufio.NewReader(sealed)
+	if _, err := data.Peek(minPasteSize); err == io.EOF {
+		return "", body.n, pasteTooSmall{}
+	} else if err != nil {
+		return "", body.n, body.check(err)
+	}
+	key, err = s.Add(data)
+	return key, body.n, body.check(err)
+}
+
+// uploadDir makes a new dir under basePath to stage an upload in.
+func uploadDir() (string, error) {
+	if err := os.MkdirAll(basePath, 0755); err != nil {
+		return "", err
+	}
+	return os.MkdirTemp(basePath, "upload-")
+}
+
+// writeDir stages an upload in a temp dir using fill, then stores the whole
+// dir as one ipfs directory and returns its hash.
+func writeDir(s store, r io.Reader, fill func(dir string, r io.Reader) error) (hash string, size int64, err error) {
+	body := &pasteReader{r: r}
+	temp_dir, err := uploadDir()
+	if err != nil {
+		return "", 0, err
+	}
+	defer os.RemoveAll(temp_dir)
+
+	if err := fill(temp_dir, body); err != nil {
+		return "", body.n, body.check(err)
+	}
+	if body.n < minPasteSize {
+		return "", body.n, pasteTooSmall{}
+	}
+	hash, err = s.AddDir(temp_dir)
+	return hash, body.n, body.check(err)
+}
+
+// writeForm stores a multipart/form-data upload. Every file part goes into
+// one directory, like curl -F f=@a -F f=@b. A form without files is a single
+// paste from the formVal field, named by the "file" field like the web form,
+// and only that can be sealed.
+func writeForm(s store, r io.Reader, boundary string, seal sealing) (key string, size int64, err error) {
+	body := &pasteReader{r: r}
+	temp_dir, err := uploadDir()
+	if err != nil {
+		return "", 0, err
+	}
+	defer os.RemoveAll(temp_dir)
+	field := temp_dir + "." + formVal // staged text field, kept out of the dir
+	defer os.Remove(field)
+
+	var name string
+	files := 0
+	mr := multipart.NewReader(body, boundary)
+	for {
+		p, err := mr.NextPart()
+		if err == io.EOF {
+			break
+		} else if err != nil {
+			return "", body.n, body.check(err)
+		}
+		switch {
+		case p.FileName() != "":
+			if !validFileName(p.FileName()) {
+				return "", body.n, badUpload("invalid file name")
+			}
+			err = saveNewFile(temp_dir, p.FileName(), p)
+			files++
+		case p.FormName() == formVal:
+			err = saveFile(path.Dir(field), path.Base(field), p)
+		case p.FormName() == "file":
+			var b []byte
+			b, err = ioutil.ReadAll(io.LimitReader(p, 255))
+			name = string(b)
+		}
 		if err != nil {
-			return "", err
+			return "", body.n, body.check(err)
 		}
+	}
 
-		words := strings.Split(string(output[:]), " ")
-		key = words[1]
+	if files == 0 {
+		f, err := os.Open(field)
+		if err != nil {
+			return "", body.n, pasteTooSmall{}
+		}
+		defer f.Close()
+		return writePaste(s, name, f, seal)
+	} else if seal.encrypt != nil {
+		return "", body.n, badUpload("only single pastes can be encrypted")
+	}
+	if body.n < minPasteSize {
+		return "", body.n, pasteTooSmall{}
 	}
+	key, err = s.AddDir(temp_dir)
+	return key, body.n, body.check(err)
+}
 
-	err = os.RemoveAll(temp_dir)
+// untar extracts a tar stream into dir. Only regular files and directories
+// are kept, and paths that would leave dir are refused. It returns how many
+// bytes were extracted, which are counted as they're written since sparse
+// members expand from nothing.
+func untar(dir string, r io.Reader) (int64, error) {
+	tr := tar.NewReader(r)
+	out := &extractReader{r: tr}
+	for files := 0; ; files++ {
+		hdr, err := tr.Next()
+		if err == io.EOF {
+			return out.n, nil
+		} else if err != nil {
+			return out.n, badUpload("bad tar archive: " + err.Error())
+		} else if files >= maxArchiveFiles {
+			return out.n, badUpload(fmt.Sprintf("archive has more than %d files", maxArchiveFiles))
+		}
+		name, ok := archivePath(hdr.Name)
+		if !ok {
+			return out.n, badUpload(fmt.Sprintf("unsafe path in archive %q", hdr.Name))
+		} else if name == "" {
+			continue
+		}
 
-	return
+		mode := hdr.FileInfo().Mode()
+		switch {
+		case mode.IsDir():
+			err = os.MkdirAll(path.Join(dir, name), 0755)
+		case mode.IsRegular():
+			if out.n+hdr.Size > maxPasteSize {
+				return out.n, badUpload(fmt.Sprintf("archive expands past %d bytes", maxPasteSize))
+			}
+			if err = os.MkdirAll(path.Join(dir, path.Dir(name)), 0755); err == nil {
+				err = saveFile(dir, name, out)
+			}
+		}
+		if err != nil {
+			return out.n, err
+		}
+	}
+}
+
+// archivePath cleans an archive member name into a path relative to the
+// extraction dir. Absolute names and names climbing out with ".." are not ok,
+// the archive root itself comes back empty.
+func archivePath(name string) (string, bool) {
+	name = strings.ReplaceAll(name, "\\", "/")
+	if path.IsAbs(name) {
+		return "", false
+	}
+	clean := path.Clean(name)
+	if clean == "." {
+		return "", true
+	} else if clean == ".." || strings.HasPrefix(clean, "../") {
+		return "", false
+	}
+	return clean, true
+}
+
+// validFileName reports whether name can be used as is inside a paste dir.
+func validFileName(name string) bool {
+	return name != "" && name != "." && name != ".." && !strings.ContainsAny(name, "/\\\x00")
+}
+
+// saveFile writes r to name inside dir.
+func saveFile(dir, name string, r io.Reader) error {
+	f, err := os.Create(path.Join(dir, name))
+	if err != nil {
+		return err
+	}
+	_, err = io.Copy(f, r)
+	if cerr := f.Close(); err == nil {
+		err = cerr
+	}
+	return err
+}
+
+// saveNewFile is saveFile, refusing to overwrite an earlier file.
+func saveNewFile(dir, name string, r io.Reader) error {
+	f, err := os.OpenFile(path.Join(dir, name), os.O_WRONLY|os.O_CREATE|os.O_EXCL, 0644)
+	if os.IsExist(err) {
+		return badUpload(fmt.Sprintf("duplicate file name %q", name))
+	} else if err != nil {
+		return err
+	}
+	_, err = io.Copy(f, r)
+	if cerr := f.Close(); err == nil {
+		err = cerr
+	}
+	return err
+}
+
+// tooLarge rejects an upload that announces a body over maxPasteSize before
+// any of it is read.
+func tooLarge(w http.ResponseWriter, req *http.Request) bool {
+	if req.ContentLength <= maxPasteSize {
+		return false
+	}
+	http.Error(w, pasteTooLarge{}.Error(), http.StatusRequestEntityTooLarge)
+	log.Printf("[ERROR] %s (error: content-length %d)\n", req.URL.Path, req.ContentLength)
+	return true
 }
 
 func Highlight(code string, lexer string, key string) (string, error) {
@@ -265,7 +743,36 @@ func Highlight(code string, lexer string, key string) (string, error) {
 	return out.String(), err
 }
 
-type handler struct{}
+type handler struct {
+	store          store
+	index          *pasteIndex
+	ids            *shortIDs
+	names          *nameIndex
+	attempts       *attemptLimiter // wrong passwords, per paste and client
+	clientAttempts *attemptLimiter // passwords tried, per client
+	compress       bool            // store text pastes zstd compressed
+}
+
+// pasteKey resolves the {hash} or {name} and {file} route vars to the root
+// cid of a paste and the full key read from it. hash may also be a short id,
+// name is a mutable ipns name.
+func (h *handler) pasteKey(vars map[string]string) (hash, key string) {
+	key = vars["hash"]
+	if target, ok := h.ids.get(key); ok {
+		key = target
+	}
+	if vars["name"] != "" {
+		rec, _ := h.names.get(vars["name"])
+		key = rec.Hash
+	}
+	if key == "" {
+		return "", ""
+	}
+	if vars["file"] != "" {
+		key = fmt.Sprintf("%s/%s", key, vars["file"])
+	}
+	return strings.SplitN(key, "/", 2)[0], key
+}
 
 func (h *handler) read(w http.ResponseWriter, req *http.Request) {
 	vars := mux.Vars(req)
@@ -273,94 +780,457 @@ func (h *handler) read(w http.ResponseWriter, req *http.Request) {
 	if useSSL {
 		w.Header().Add("Strict-Transport-Security", "max-age=63072000; includeSubDomains") //ssl lab bullshit
 	}
-	if vars["hash"] != "" {
-		var key string
-		if vars["file"] != "" {
-			key = fmt.Sprintf("%s/%s", vars["hash"], vars["file"])
-		} else {
-			key = vars["hash"]
+	w.Header().Set("X-Content-Type-Options", "nosniff")
+	secret := takeSecret(req)
+	if hash, key := h.pasteKey(vars); hash != "" {
+		meta, _ := h.index.get(hash)
+		if meta.gone(time.Now()) {
+			http.Error(w, pasteGone{}.Error(), http.StatusGone)
+			log.Printf("[GONE ] %s\n", key)
+			return
+		}
+		if _, ok := req.URL.Query()["history"]; ok && vars["name"] != "" {
+			w.Header().Set("Cache-Control", "no-cache")
+			h.history(w, req, vars["name"])
+			return
+		}
+
+		info, err := h.store.Stat(key)
+		var member string
+		if err != nil && !meta.sealed() {
+			// maybe a path running on inside an uploaded archive, unless
+			// it would take decrypting the whole archive to find it
+			if archive, inside, ok := splitArchivePath(key); ok {
+				if ainfo, aerr := h.store.Stat(archive); aerr == nil && !ainfo.IsDir {
+					key, member, info, err = archive, inside, ainfo, nil
+				}
+			}
 		}
-		paste, err := readPaste(key)
 		if err != nil {
-			if _, ok := err.(pasteNotFound); ok {
-				http.Error(w, "not found", http.StatusNotFound)
+			http.Error(w, "not found", http.StatusNotFound)
+			log.Printf("[ERROR] %s (%s)\n", key, err.Error())
+			return
+		}
+
+		// diff refuses view limited pastes, so it goes before a view is
+		// counted
+		if old := req.URL.Query().Get("diff"); old != "" && member == "" && !info.IsDir {
+			_, oldKey := h.pasteKey(map[string]string{"hash": old, "file": vars["file"]})
+			h.diff(w, req, oldKey, key)
+			return
+		}
+
+		if _, ls := req.URL.Query()["ls"]; ls && archiveKind(key) != "" && meta.sealed() {
+			http.Error(w, "encrypted archives can't be browsed", http.StatusUnprocessableEntity)
+			log.Printf("[ERROR] %s (ls of an encrypted archive)\n", key)
+			return
+		}
+
+		// check the key or password before a view is counted
+		var plain []byte
+		if meta.PasswordSalt != "" && !info.IsDir {
+			var ok bool
+			if plain, ok = h.unlock(w, req, hash, key, meta); !ok {
+				return
+			}
+		} else if meta.Encrypted && !info.IsDir {
+			var aead cipher.AEAD
+			sealed, err := readPaste(h.store, key)
+			if err == nil {
+				aead, err = secretAEAD(secret)
+			}
+			if err == nil {
+				plain, err = decryptPaste(sealed, aead)
+			}
+			if err != nil {
+				http.Error(w, decryptDenied{}.Error(), http.StatusForbidden)
+				log.Printf("[ERROR] %s (%s)\n", key, err.Error())
+				return
+			}
+		}
+
+		if meta.MaxViews > 0 && req.Method != "HEAD" {
+			// count the view before serving it, so concurrent reads can't
+			// go over the limit
+			last := false
+			err := h.index.update(hash, func(m *pasteMeta, ok bool) error {
+				if m.gone(time.Now()) {
+					return pasteGone{}
+				}
+				m.Views++
+				last = m.Views >= m.MaxViews
+				return nil
+			})
+			if err != nil {
+				http.Error(w, pasteGone{}.Error(), http.StatusGone)
+				log.Printf("[GONE ] %s\n", key)
+				return
+			}
+			if last {
+				defer h.index.remove(h.store, hash)
+			}
+		}
+		log.Printf("[READ ] %s\n", key)
+
+		if meta.MaxViews > 0 || meta.Encrypted || meta.PasswordSalt != "" {
+			// encrypted pastes are served decrypted, which no shared cache
+			// should keep
+			w.Header().Set("Cache-Control", "private, no-store")
+		} else if vars["name"] != "" || h.ids.isSlug(vars["hash"]) {
+			// names and slugs move, check back every time but the ETag
+			// still works
+			w.Header().Set("Cache-Control", "no-cache")
+		} else {
+			// keys are content addressed, the paste behind one can never
+			// change, it can only go away
+			maxAge := int64(365 * 24 * time.Hour / time.Second)
+			if !meta.Expires.IsZero() {
+				if left := int64(time.Until(meta.Expires) / time.Second); left < maxAge {
+					maxAge = left
+				}
+			}
+			w.Header().Set("Cache-Control", fmt.Sprintf("public, max-age=%d, immutable", maxAge))
+		}
+
+		if info.IsDir {
+			if format := archiveFormat(req); format != "" {
+				h.archive(w, req, key, format, meta.Created)
 			} else {
+				h.list(w, req, key, info)
+			}
+			return
+		}
+
+		if meta.ClientEncrypted {
+			w.Header().Set("Vary", "Accept")
+			if wantsHTML(req) {
+				h.zkPage(w, key)
+				return
+			}
+		}
+
+		if meta.Encoding != "" && plain == nil && member == "" {
+			if req.URL.RawQuery == "" && acceptsEncoding(req, meta.Encoding) {
+				h.serveStored(w, req, key, info, meta.Encoding)
+				return
+			}
+			stored, err := readPaste(h.store, key)
+			if err == nil {
+				plain, err = decodePaste(stored, meta.Encoding)
+			}
+			if err != nil {
 				http.Error(w, err.Error(), http.StatusInternalServerError)
+				log.Printf("[ERROR] %s (%s: %s)\n", key, meta.Encoding, err.Error())
+				return
 			}
-			log.Printf("[ERROR] %s (%s)\n", key, err.Error())
+		}
+
+		if plain != nil {
+			if req.URL.RawQuery != "" {
+				render(w, req, key, plain)
+				return
+			}
+			w.Header().Set("ETag", fmt.Sprintf("%q", info.Hash))
+			setContentType(w, info.Name, func() []byte {
+				return plain[:min(len(plain), 512)]
+			})
+			serveEncoded(w, req, int64(len(plain)), bytes.NewReader(plain))
+			return
+		}
+
+		if _, ls := req.URL.Query()["ls"]; member != "" || (ls && archiveKind(key) != "") {
+			h.browse(w, req, key, member, info)
 			return
 		}
-		log.Printf("[READ ] %s\n", key)
 
 		if req.URL.RawQuery != "" {
-			w.Header().Set("Content-Type", "text/html; charset=utf-8")
-			switch req.URL.RawQuery {
-			case "md":
-				paste = md.Markdown([]byte(paste))
-			default:
-				syntax, err := Highlight(string(paste), req.URL.RawQuery, key)
-				if err == nil {
-					paste = []byte(syntax)
-				} else {
-					fmt.Fprintf(w, "error: %s", pygmentsError{}.Error())
-					return
-				}
+			paste, err := readPaste(h.store, key)
+			if err != nil {
+				http.Error(w, err.Error(), http.StatusInternalServerError)
+				log.Printf("[ERROR] %s (%s)\n", key, err.Error())
+				return
 			}
+			render(w, req, key, paste)
+			return
 		}
 
-		fmt.Fprintf(w, "%s", paste)
-		return
+		// raw pastes are streamed, ServeContent handles HEAD, Range and
+		// If-None-Match against the ETag, and they're compressed on the way
+		// if the client wants
+		w.Header().Set("ETag", fmt.Sprintf("%q", info.Hash))
+		f := &pasteFile{s: h.store, key: key, size: info.Size}
+		defer f.Close()
+		setContentType(w, info.Name, func() []byte {
+			head := make([]byte, 512)
+			n, _ := io.ReadFull(f, head)
+			f.Seek(0, io.SeekStart)
+			return head[:n]
+		})
+		serveEncoded(w, req, info.Size, f)
+	} else {
+		http.Error(w, "not found", http.StatusNotFound)
+		log.Printf("[ERROR] %s (unknown name)\n", req.URL.Path)
+	}
+}
+
+// render writes paste as html, as markdown with ?md or highlighted by the
+// pygments lexer named in the query.
+func render(w http.ResponseWriter, req *http.Request, key string, paste []byte) {
+	w.Header().Set("Content-Type", "text/html; charset=utf-8")
+	switch req.URL.RawQuery {
+	case "md":
+		paste = md.Markdown([]byte(paste))
+	default:
+		syntax, err := Highlight(string(paste), req.URL.RawQuery, key)
+		if err == nil {
+			paste = []byte(syntax)
+		} else {
+			fmt.Fprintf(w, "error: %s", pygmentsError{}.Error())
+			return
+		}
 	}
+
+	fmt.Fprintf(w, "%s", paste)
 }
 
 func (h *handler) post(w http.ResponseWriter, req *http.Request) {
 	vars := mux.Vars(req)
+	if tooLarge(w, req) {
+		return
+	}
+
+	mediaType, params, _ := mime.ParseMediaType(req.Header.Get("Content-Type"))
+	if mediaType == "multipart/form-data" {
+		h.upload(w, req, func(seal sealing) (string, int64, error) {
+			if uploadFlag(req, "zk") {
+				return "", 0, badUpload("browser encrypted pastes are posted by the web form")
+			}
+			return writeForm(h.store, req.Body, params["boundary"], seal)
+		})
+		return
+	}
+
+	// ParseForm buffers at most 10MB and then leaves the field empty, bound
+	// the body here instead so an oversized paste gets a 413
+	pr := &pasteReader{r: req.Body}
+	req.Body = http.MaxBytesReader(w, readCloser{pr, req.Body}, maxPasteSize+1)
+	if err := pr.check(req.ParseForm()); err != nil {
+		if _, ok := err.(pasteTooLarge); ok {
+			http.Error(w, err.Error(), http.StatusRequestEntityTooLarge)
+		} else {
+			http.Error(w, err.Error(), http.StatusBadRequest)
+		}
+		log.Printf("[ERROR] %s (error: %s)\n", req.URL.Path, err.Error())
+		return
+	}
 	body := req.FormValue(formVal)
+	if _, ticked := req.PostForm["zk"]; ticked && !uploadFlag(req, "zk") {
+		// the encrypt box was ticked but nothing encrypted the paste, eg.
+		// with javascript off, so don't store it in the clear
+		http.Error(w, "encrypting in the browser needs javascript, nothing was pasted", http.StatusBadRequest)
+		log.Printf("[ERROR] %s (error: zk form field without ?zk)\n", req.URL.Path)
+		return
+	}
+	if _, ok := parseZKPaste([]byte(body)); uploadFlag(req, "zk") && !ok {
+		http.Error(w, "not a browser encrypted paste", http.StatusBadRequest)
+		log.Printf("[ERROR] %s (error: bad zk paste)\n", req.URL.Path)
+		return
+	}
+	name := vars["file"]
+	if name == "" {
+		name = req.FormValue("file")
+	}
+	h.upload(w, req, func(seal sealing) (string, int64, error) {
+		return writePaste(h.store, name, strings.NewReader(body), seal)
+	})
+}
 
-	key, err := writePaste(vars["file"], []byte(body))
+func (h *handler) put(w http.ResponseWriter, req *http.Request) {
+	vars := mux.Vars(req)
+	if tooLarge(w, req) {
+		return
+	}
+	if uploadFlag(req, "zk") {
+		http.Error(w, "browser encrypted pastes are posted by the web form", http.StatusBadRequest)
+		return
+	}
+	if err := decodeBody(req); err != nil {
+		if _, ok := err.(unknownEncoding); ok {
+			w.Header().Set("Accept-Encoding", strings.Join(responseEncodings, ", "))
+			http.Error(w, err.Error(), http.StatusUnsupportedMediaType)
+		} else {
+			http.Error(w, err.Error(), http.StatusBadRequest)
+		}
+		log.Printf("[ERROR] %s (error: %s)\n", req.URL.Path, err.Error())
+		return
+	}
+
+	if uploadFlag(req, "extract") {
+		h.upload(w, req, func(seal sealing) (string, int64, error) {
+			if seal.encrypt != nil {
+				return "", 0, badUpload("only single pastes can be encrypted")
+			}
+			// size the paste by what it expanded to, not the compressed body
+			var expanded int64
+			hash, _, err := writeDir(h.store, req.Body, func(dir string, r io.Reader) (err error) {
+				expanded, err = extract(dir, r)
+				return err
+			})
+			return hash, expanded, err
+		})
+		return
+	}
+	if uploadFlag(req, "tar") || req.Header.Get("Content-Type") == "application/x-tar" {
+		h.upload(w, req, func(seal sealing) (string, int64, error) {
+			if seal.encrypt != nil {
+				return "", 0, badUpload("only single pastes can be encrypted")
+			}
+			var expanded int64
+			hash, _, err := writeDir(h.store, req.Body, func(dir string, r io.Reader) (err error) {
+				expanded, err = untar(dir, r)
+				return err
+			})
+			return hash, expanded, err
+		})
+		return
+	}
+	h.upload(w, req, func(seal sealing) (string, int64, error) {
+		return writePaste(h.store, vars["file"], req.Body, seal)
+	})
+}
+
+// upload stores a paste from post or put using write, records its metadata
+// and replies with the paste url. write passes single pastes through seal.
+func (h *handler) upload(w http.ResponseWriter, req *http.Request, write func(seal sealing) (key string, size int64, err error)) {
+	opts, err := parseUploadOptions(req)
+	if err != nil {
+		http.Error(w, err.Error(), http.StatusBadRequest)
+		log.Printf("[ERROR] %s (error: %s)\n", req.URL.Path, err.Error())
+		return
+	}
+
+	// catch taken slugs and bad update tokens before storing anything,
+	// claiming and publishing check again
+	if opts.Slug != "" && !h.ids.canClaim(opts.Slug, requestToken(req)) {
+		http.Error(w, slugTaken{}.Error(), http.StatusConflict)
+		log.Printf("[ERROR] %s (error: %s)\n", req.URL.Path, slugTaken{}.Error())
+		return
+	}
+	if opts.Name != "" {
+		if rec, ok := h.names.get(opts.Name); !ok {
+			http.Error(w, "not found", http.StatusNotFound)
+			log.Printf("[ERROR] %s (unknown name)\n", req.URL.Path)
+			return
+		} else if !tokenMatches(rec.Token, opts.UpdateToken) {
+			http.Error(w, updateDenied{}.Error(), http.StatusForbidden)
+			log.Printf("[ERROR] %s (error: %s)\n", req.URL.Path, updateDenied{}.Error())
+			return
+		}
+	}
+
+	// encrypted pastes are sealed on their way into the store, under a new
+	// key for ?encrypt or one derived from the password
+	var secret, salt, encoding string
+	var seal sealing
+	if opts.Encrypt || opts.Password != "" {
+		var aead cipher.AEAD
+		if opts.Encrypt {
+			if secret, err = newSecret(); err == nil {
+				aead, err = secretAEAD(secret)
+			}
+		} else {
+			aead, salt, err = newPasswordAEAD(opts.Password)
+		}
+		if err != nil {
+			http.Error(w, err.Error(), http.StatusInternalServerError)
+			log.Printf("[ERROR] %s (error: %s)\n", req.URL.Path, err.Error())
+			return
+		}
+		seal.encrypt = encryptSealer(aead)
+	} else if len(opts.Recipients) > 0 {
+		seal.encrypt = ageSealer(opts.Recipients)
+	} else if h.compress && !opts.ClientEncrypted {
+		seal.compress = compressSealer(&encoding)
+	}
+
+	key, size, err := write(seal)
 	if err != nil {
 		switch err.(type) {
-		case pasteTooLarge, pasteTooSmall:
+		case pasteTooLarge:
+			http.Error(w, err.Error(), http.StatusRequestEntityTooLarge)
+		case pasteTooSmall:
 			http.Error(w, err.Error(), http.StatusNotAcceptable)
+		case badUpload:
+			http.Error(w, err.Error(), http.StatusBadRequest)
 		default:
 			http.Error(w, err.Error(), http.StatusInternalServerError)
 		}
-		log.Printf("[ERROR] %s (error: %s)\n", vars["file"], err.Error())
+		log.Printf("[ERROR] %s (error: %s)\n", req.URL.Path, err.Error())
 		return
 	}
-	log.Printf("[WRITE] %s\n", key)
-	var scheme string
-	if req.TLS != nil {
-		scheme = "https://"
-	} else {
-		scheme = "http://"
-	}
-	fmt.Fprintf(w, "%s%s/%s\n", scheme, req.Host, key)
-	return
-}
 
-func (h *handler) put(w http.ResponseWriter, req *http.Request) {
-	vars := mux.Vars(req)
+	token, err := newDeleteToken()
+	if err != nil {
+		http.Error(w, err.Error(), http.StatusInternalServerError)
+		log.Printf("[ERROR] %s (error: %s)\n", key, err.Error())
+		return
+	}
 
-	body, err := ioutil.ReadAll(req.Body)
+	hash := strings.SplitN(key, "/", 2)[0]
+	var meta pasteMeta
+	err = h.index.update(hash, func(m *pasteMeta, ok bool) error {
+		if err := m.add(time.Now(), pasteExpiry(opts.Expires, size), opts.MaxViews, ok); err != nil {
+			return err
+		}
+		m.DeleteTokens = append(m.DeleteTokens, sha256Hex([]byte(token)))
+		m.Encrypted = m.Encrypted || opts.Encrypt
+		m.ClientEncrypted = m.ClientEncrypted || opts.ClientEncrypted
+		m.AgeEncrypted = m.AgeEncrypted || len(opts.Recipients) > 0
+		if salt != "" {
+			m.PasswordSalt = salt
+		}
+		if encoding != "" {
+			m.Encoding = encoding
+		}
+		meta = *m
+		return nil
+	})
 	if err != nil {
-		fmt.Fprint(w, "an error occurred")
+		if _, ok := err.(viewsConflict); ok {
+			http.Error(w, err.Error(), http.StatusConflict)
+		} else {
+			http.Error(w, err.Error(), http.StatusInternalServerError)
+		}
+		log.Printf("[ERROR] %s (error: %s)\n", key, err.Error())
 		return
 	}
 
-	key, err := writePaste(vars["file"], body)
+	var id, updateToken string
+	switch {
+	case opts.Publish || opts.Name != "":
+		var name string
+		name, updateToken, err = h.names.publish(h.store, opts.Name, hash, opts.UpdateToken)
+		id = "ipns/" + name
+	case opts.Slug != "":
+		id = opts.Slug
+		err = h.ids.claim(id, hash, token, requestToken(req))
+	default:
+		id, err = h.ids.add(hash)
+	}
 	if err != nil {
 		switch err.(type) {
-		case pasteTooLarge, pasteTooSmall:
-			http.Error(w, err.Error(), http.StatusNotAcceptable)
+		case slugTaken:
+			http.Error(w, err.Error(), http.StatusConflict)
+		case updateDenied:
+			http.Error(w, err.Error(), http.StatusForbidden)
 		default:
 			http.Error(w, err.Error(), http.StatusInternalServerError)
 		}
-		log.Printf("[ERROR] %s (error: %s)\n", vars["file"], err.Error())
+		log.Printf("[ERROR] %s (short id: %s)\n", key, err.Error())
 		return
 	}
 
-	log.Printf("[WRITE] %s (%s)\n", vars["file"], key)
+	log.Printf("[WRITE] %s (%s as %s)\n", req.URL.Path, key, id)
 
 	var scheme string
 	if req.TLS != nil {
@@ -368,10 +1238,199 @@ func (h *handler) put(w http.ResponseWriter, req *http.Request) {
 	} else {
 		scheme = "http://"
 	}
-	fmt.Fprintf(w, "%s%s/%s\n", scheme, req.Host, key)
+	result := uploadResult{
+		URL:         fmt.Sprintf("%s%s/%s%s", scheme, req.Host, id, strings.TrimPrefix(key, hash)),
+		Path:        "/ipfs/" + key,
+		DeleteToken: token,
+	}
+	if secret != "" {
+		// the server keeps no copy of the key, the url is the only one
+		result.URL += "?key=" + secret
+	}
+	result.Recipients = opts.To
+	for _, to := range opts.To {
+		w.Header().Add("X-Age-Recipient", to)
+	}
+	w.Header().Set("X-Ipfs-Path", result.Path)
+	w.Header().Set("X-Delete-Token", token)
+	if updateToken != "" {
+		result.UpdateToken = updateToken
+		w.Header().Set("X-Update-Token", updateToken)
+	}
+	if !meta.Expires.IsZero() {
+		result.Expires = meta.Expires.UTC().Format(time.RFC3339)
+		w.Header().Set("X-Expires", result.Expires)
+	}
+	if meta.MaxViews > 0 {
+		result.Views = meta.MaxViews - meta.Views
+		w.Header().Set("X-Views", strconv.Itoa(result.Views))
+	}
+
+	if wantsJSON(req) {
+		w.Header().Set("Content-Type", "application/json")
+		json.NewEncoder(w).Encode(result)
+		return
+	}
+	fmt.Fprintf(w, "%s\n", result.URL)
+}
+
+// uploadResult is the json reply to an upload. Plain text replies only
+// carry the url, the rest is sent in X- headers.
+type uploadResult struct {
+	URL         string   `json:"url"`
+	Path        string   `json:"ipfs_path"`
+	DeleteToken string   `json:"delete_token"`
+	UpdateToken string   `json:"update_token,omitempty"`
+	Recipients  []string `json:"recipients,omitempty"`
+	Expires     string   `json:"expires,omitempty"`
+	Views       int      `json:"views,omitempty"`
+}
+
+func wantsJSON(req *http.Request) bool {
+	_, ok := req.URL.Query()["json"]
+	return ok || strings.Contains(req.Header.Get("Accept"), "application/json")
+}
+
+func (h *handler) delete(w http.ResponseWriter, req *http.Request) {
+	hash, _ := h.pasteKey(mux.Vars(req))
+	token := requestToken(req)
+
+	var remaining int
+	err := h.index.update(hash, func(m *pasteMeta, ok bool) error {
+		if !ok {
+			return pasteNotFound{}
+		} else if m.Removed {
+			return pasteGone{}
+		} else if !m.removeDeleteToken(token) {
+			return deleteDenied{}
+		}
+		// identical uploads share the paste, it stays up for the others
+		if remaining = len(m.DeleteTokens); remaining > 0 {
+			return nil
+		}
+		// unpinning through the store also drops it from the read cache
+		h.index.unpin(h.store, hash, m)
+		m.Deleted = true
+		return nil
+	})
+	if err != nil {
+		switch err.(type) {
+		case pasteGone:
+			http.Error(w, err.Error(), http.StatusGone)
+		case deleteDenied:
+			http.Error(w, err.Error(), http.StatusForbidden)
+		default:
+			http.Error(w, pasteNotFound{}.Error(), http.StatusNotFound)
+		}
+		log.Printf("[ERROR] %s (delete: %s)\n", hash, err.Error())
+		return
+	}
+
+	if remaining > 0 {
+		log.Printf("[DEL  ] %s (%d uploads left)\n", hash, remaining)
+		fmt.Fprintf(w, "deleted your upload of %s, it stays up for %d other uploads\n", hash, remaining)
+		return
+	}
+	log.Printf("[DEL  ] %s\n", hash)
+	fmt.Fprintf(w, "deleted %s\n", hash)
+}
+
+// uploadOptions are per paste settings, given as a query parameter
+// (?expires=1h) or the matching X- header (X-Expires: 1h).
+type uploadOptions struct {
+	Expires     time.Duration
+	MaxViews    int    // 0 = unlimited
+	Slug        string // vanity slug to claim instead of a short id
+	Publish     bool   // publish under a new mutable ipns name
+	Name        string // existing ipns name to repoint, with UpdateToken
+	UpdateToken string
+	Encrypt     bool   // encrypt before storing, the key only goes in the url
+	Password    string // encrypt before storing, with a key from the password
+	To          []string
+	Recipients  []age.Recipient // encrypt with age to these, parsed from To
+
+	// already encrypted by the web form, the key never reaches the server
+	ClientEncrypted bool
+}
+
+func uploadOption(req *http.Request, name string) string {
+	if v := req.URL.Query().Get(name); v != "" {
+		return v
+	}
+	return req.Header.Get("X-" + name)
+}
+
+// uploadFlag reports whether a valueless option (?burn) was given.
+func uploadFlag(req *http.Request, name string) bool {
+	_, ok := req.URL.Query()[name]
+	return ok || req.Header.Get("X-"+name) != ""
+}
+
+func parseUploadOptions(req *http.Request) (opts uploadOptions, err error) {
+	if v := uploadOption(req, "expires"); v != "" {
+		if opts.Expires, err = parseExpiry(v); err != nil {
+			return opts, fmt.Errorf("bad expiry %q, use eg. 30m, 12h or 7d", v)
+		}
+	}
+	if v := uploadOption(req, "views"); v != "" {
+		if opts.MaxViews, err = strconv.Atoi(v); err != nil || opts.MaxViews < 1 {
+			return opts, fmt.Errorf("bad view count %q", v)
+		}
+	}
+	if uploadFlag(req, "burn") {
+		opts.MaxViews = 1
+	}
+	opts.Slug = uploadOption(req, "slug")
+	if v := mux.Vars(req)["slug"]; v != "" {
+		opts.Slug = v
+	}
+	if opts.Slug != "" {
+		err = validSlug(opts.Slug)
+	}
+	opts.Publish = uploadFlag(req, "ipns")
+	opts.Name = mux.Vars(req)["name"]
+	opts.UpdateToken = uploadOption(req, "update-token")
+	opts.Encrypt = uploadFlag(req, "encrypt")
+	opts.ClientEncrypted = uploadFlag(req, "zk")
+	// never from the url, where it would be logged
+	if _, ok := req.URL.Query()["password"]; ok {
+		return opts, errors.New("send the password with curl -u or X-Password, not in the url")
+	}
+	opts.Password = req.Header.Get("X-Password")
+	if _, password, ok := req.BasicAuth(); ok && opts.Password == "" {
+		// curl -u :<password>
+		opts.Password = password
+	}
+	opts.To = append(req.URL.Query()["to"], req.Header.Values("X-To")...)
+	for _, to := range opts.To {
+		r, rerr := parseRecipient(to)
+		if rerr != nil && err == nil {
+			err = rerr
+		}
+		opts.Recipients = append(opts.Recipients, r)
+	}
+
+	// each of these encrypts the paste its own way
+	modes := 0
+	for _, on := range []bool{opts.Encrypt, opts.ClientEncrypted, opts.Password != "", len(opts.To) > 0} {
+		if on {
+			modes++
+		}
+	}
+	if err == nil && modes > 1 {
+		err = errors.New("only one of ?encrypt, ?zk, ?to or a password can be used")
+	}
 	return
 }
 
+// requestToken is the deletion token presented with a request.
+func requestToken(req *http.Request) string {
+	if token := req.Header.Get("X-Delete-Token"); token != "" {
+		return token
+	}
+	return req.URL.Query().Get("token")
+}
+
 func (h *handler) usage(w http.ResponseWriter, req *http.Request) {
 	vars := mux.Vars(req)
 	var usageText string
@@ -412,8 +1471,13 @@ func (h *handler) usage(w http.ResponseWriter, req *http.Request) {
 	}
 }
 
-func newHandler() http.Handler {
-	h := handler{}
+func newHandler(s store, idx *pasteIndex, ids *shortIDs, names *nameIndex, compress bool) http.Handler {
+	h := handler{
+		store: s, index: idx, ids: ids, names: names,
+		attempts:       newAttemptLimiter(maxPasswordAttempts),
+		clientAttempts: newAttemptLimiter(maxClientAttempts),
+		compress:       compress,
+	}
 	r := mux.NewRouter().StrictSlash(false)
 
 	// certbot existing web server
@@ -421,10 +1485,21 @@ func newHandler() http.Handler {
 
 	r.HandleFunc("/", h.usage).Methods("GET")
 
-	r.HandleFunc("/{hash}", h.read).Methods("GET")
-	r.HandleFunc("/{hash}/{file}", h.read).Methods("GET")
+	r.HandleFunc("/diff/{a}/{b}", h.diffPastes).Methods("GET")
+	r.HandleFunc("/ipns/{name}", h.read).Methods("GET", "HEAD")
+	r.HandleFunc("/ipns/{name}/{file:.*}", h.read).Methods("GET", "HEAD")
+	r.HandleFunc("/{hash}", h.read).Methods("GET", "HEAD")
+	r.HandleFunc("/{hash}/{file:.*}", h.read).Methods("GET", "HEAD")
+	// the password prompt posts back to the paste
+	r.HandleFunc("/{hash}", h.read).Methods("POST")
+	r.HandleFunc("/{hash}/{file:.*}", h.read).Methods("POST")
+
+	r.HandleFunc("/{hash}", h.delete).Methods("DELETE")
+	r.HandleFunc("/{hash}/{file:.*}", h.delete).Methods("DELETE")
 
 	r.HandleFunc("/", h.post).Methods("POST")
+	r.HandleFunc("/s/{slug}", h.put).Methods("PUT")
+	r.HandleFunc("/ipns/{name}", h.put).Methods("PUT")
 	r.HandleFunc("/{file}", h.put).Methods("PUT")
 	r.HandleFunc("/", h.put).Methods("PUT")
 
@@ -432,13 +1507,48 @@ func newHandler() http.Handler {
 }
 
 func main() {
+	backend := flag.String("store", storeType, "storage backend (ipfs, disk, s3 or mem)")
+	apiAddr := flag.String("ipfs-api", ipfsAPI, "ipfs daemon rpc api address")
+	diskPath := flag.String("store-path", storePath, "block directory for the disk store")
+	cacheDir := flag.String("cache-dir", cachePath, "read cache directory, empty to disable")
+	indexDir := flag.String("index-dir", indexPath, "paste metadata directory")
+	idDir := flag.String("id-dir", idPath, "short url id directory")
+	nameDir := flag.String("name-dir", namePath, "mutable ipns name directory")
+	compress := flag.Bool("zstd", compressText, "store text pastes zstd compressed")
+	adminAddr := flag.String("admin-addr", adminAddress, "address to serve /debug/vars on, empty to disable")
+	s3 := s3Config{
+		AccessKey: os.Getenv("AWS_ACCESS_KEY_ID"),
+		SecretKey: os.Getenv("AWS_SECRET_ACCESS_KEY"),
+	}
+	flag.StringVar(&s3.Endpoint, "s3-endpoint", "https://s3.amazonaws.com", "s3 compatible endpoint url")
+	flag.StringVar(&s3.Bucket, "s3-bucket", "", "s3 bucket to store blocks in")
+	flag.StringVar(&s3.Region, "s3-region", s3Region, "s3 region used for request signing")
+	flag.Parse()
 	rand.Seed(time.Now().UTC().UnixNano())
 
-	http.Handle("/", newHandler())
+	s, err := newStore(*backend, *apiAddr, *diskPath, s3)
+	if err != nil {
+		log.Fatal(err)
+	}
+	if *cacheDir != "" {
+		s = newCachedStore(s, *cacheDir, cacheSize)
+	}
+
+	idx := newPasteIndex(*indexDir)
+	go reaper(s, idx, reapInterval)
+
+	// not http.DefaultServeMux, expvar puts /debug/vars on that
+	public := http.NewServeMux()
+	public.Handle("/", newHandler(s, idx, newShortIDs(*idDir), newNameIndex(*nameDir), *compress))
+	if *adminAddr != "" {
+		admin := http.NewServeMux()
+		admin.Handle("/debug/vars", expvar.Handler())
+		go func() { log.Print(http.ListenAndServe(*adminAddr, admin)) }()
+	}
 	if useSSL {
 		httpsAddr := fmt.Sprintf("%s:%d", bindAddress, httpsPort)
-		go http.ListenAndServeTLS(httpsAddr, sslCertPath, sslKeyPath, nil) //goroutine ssl server alongside other shit
+		go http.ListenAndServeTLS(httpsAddr, sslCertPath, sslKeyPath, public) //goroutine ssl server alongside other shit
 	}
 	httpAddr := fmt.Sprintf("%s:%d", bindAddress, httpPort)
-	fmt.Print(http.ListenAndServe(httpAddr, nil))
+	fmt.Print(http.ListenAndServe(httpAddr, public))
 }
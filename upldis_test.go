@@ -0,0 +1,1574 @@
+package main
+
+import (
+	"archive/tar"
+	"archive/zip"
+	"bytes"
+	"compress/gzip"
+	"encoding/base64"
+	"encoding/json"
+	"encoding/xml"
+	"fmt"
+	"io"
+	"io/ioutil"
+	"math"
+	"mime/multipart"
+	"net/http"
+	"net/http/httptest"
+	"net/url"
+	"os"
+	"path/filepath"
+	"sort"
+	"strconv"
+	"strings"
+	"sync"
+	"testing"
+	"time"
+
+	"filippo.io/age"
+	"filippo.io/age/armor"
+)
+
+const testPaste = "the quick brown fox jumps over the lazy dog\n"
+
+func newTestServer(t *testing.T) *httptest.Server {
+	srv, _, _ := newTestServerWith(t)
+	return srv
+}
+
+// newTestServerWith also returns the server's store and index.
+func newTestServerWith(t *testing.T) (*httptest.Server, store, *pasteIndex) {
+	s, idx := newMemStore(), newPasteIndex(t.TempDir())
+	srv := httptest.NewServer(newHandler(s, idx, newShortIDs(t.TempDir()), newNameIndex(t.TempDir()), false))
+	t.Cleanup(srv.Close)
+	return srv, s, idx
+}
+
+// pasteHash is the root cid of the paste an upload response is for.
+func pasteHash(resp *http.Response) string {
+	p := strings.TrimPrefix(resp.Header.Get("X-Ipfs-Path"), "/ipfs/")
+	return strings.SplitN(p, "/", 2)[0]
+}
+
+func doRequest(t *testing.T, method, url string, body io.Reader, header http.Header) (*http.Response, string) {
+	t.Helper()
+	req, err := http.NewRequest(method, url, body)
+	if err != nil {
+		t.Fatal(err)
+	}
+	for k, v := range header {
+		req.Header[k] = v
+	}
+	resp, err := http.DefaultClient.Do(req)
+	if err != nil {
+		t.Fatal(err)
+	}
+	defer resp.Body.Close()
+	b, err := ioutil.ReadAll(resp.Body)
+	if err != nil {
+		t.Fatal(err)
+	}
+	return resp, string(b)
+}
+
+func TestMemStoreCid(t *testing.T) {
+	// `echo "hello world" | ipfs add`
+	key, err := newMemStore().Add(strings.NewReader("hello world\n"))
+	if err != nil {
+		t.Fatal(err)
+	}
+	if want := "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o"; key != want {
+		t.Fatalf("got cid %s, want %s", key, want)
+	}
+}
+
+func TestMemStoreDirCid(t *testing.T) {
+	// `ipfs add -r dir` of dir/hello.txt, then with dir/sub/hello.txt too
+	dir := t.TempDir()
+	if err := os.WriteFile(filepath.Join(dir, "hello.txt"), []byte("hello world\n"), 0644); err != nil {
+		t.Fatal(err)
+	}
+	key, err := newMemStore().AddDir(dir)
+	if err != nil {
+		t.Fatal(err)
+	}
+	if want := "QmfLiVjH2vujCVP2e75zyzBYmpcjktmDeU1YBz6Ct8BBsc"; key != want {
+		t.Fatalf("one file: got cid %s, want %s", key, want)
+	}
+
+	if err := os.Mkdir(filepath.Join(dir, "sub"), 0755); err != nil {
+		t.Fatal(err)
+	}
+	if err := os.WriteFile(filepath.Join(dir, "sub", "hello.txt"), []byte("hello world\n"), 0644); err != nil {
+		t.Fatal(err)
+	}
+	if key, err = newMemStore().AddDir(dir); err != nil {
+		t.Fatal(err)
+	}
+	if want := "QmZDrFTbbmwiJbQMRtBzmt8bkPkwXanK5ERcA4aTmZweTR"; key != want {
+		t.Fatalf("with a subdir: got cid %s, want %s", key, want)
+	}
+}
+
+func TestMemStoreLargeFile(t *testing.T) {
+	s := newMemStore()
+	data := strings.Repeat("0123456789abcdef", 3*chunkSize/16+7)
+	key, err := s.Add(strings.NewReader(data))
+	if err != nil {
+		t.Fatal(err)
+	}
+	for _, rng := range [][2]int64{{0, -1}, {chunkSize - 5, 10}, {2*chunkSize + 3, -1}} {
+		r, err := s.Cat(key, rng[0], rng[1])
+		if err != nil {
+			t.Fatal(err)
+		}
+		got, err := ioutil.ReadAll(r)
+		r.Close()
+		if err != nil {
+			t.Fatal(err)
+		}
+		want := data[rng[0]:]
+		if rng[1] >= 0 {
+			want = want[:rng[1]]
+		}
+		if string(got) != want {
+			t.Fatalf("range %v: read back %d bytes, want %d", rng, len(got), len(want))
+		}
+	}
+}
+
+// testBlockGC stores two pastes in s, reads them back, and checks GC frees
+// every block of the one that's unpinned and none of the other.
+func testBlockGC(t *testing.T, s *dagStore) {
+	t.Helper()
+	big := strings.Repeat("0123456789abcdef", 3*chunkSize/16+7)
+	var keys []string
+	for _, data := range []string{big, testPaste} {
+		key, err := s.Add(strings.NewReader(data))
+		if err != nil {
+			t.Fatal(err)
+		}
+		r, err := s.Cat(key, 0, -1)
+		if err != nil {
+			t.Fatal(err)
+		}
+		got, err := ioutil.ReadAll(r)
+		r.Close()
+		if err != nil || string(got) != data {
+			t.Fatalf("read back %d bytes of %d, %v", len(got), len(data), err)
+		}
+		keys = append(keys, key)
+	}
+	before, err := s.blocks.Keys()
+	if err != nil {
+		t.Fatal(err)
+	}
+
+	if err := s.Unpin(keys[0]); err != nil {
+		t.Fatal(err)
+	}
+	if err := s.GC(); err != nil {
+		t.Fatal(err)
+	}
+	if _, err := s.Stat(keys[0]); err == nil {
+		t.Fatal("unpinned paste survived gc")
+	}
+	if _, err := s.Stat(keys[1]); err != nil {
+		t.Fatalf("pinned paste: %v", err)
+	}
+	after, err := s.blocks.Keys()
+	if err != nil {
+		t.Fatal(err)
+	}
+	// a file block and its pin marker are left
+	if len(after) != 2 || len(before) <= len(after) {
+		t.Fatalf("%d blocks before gc, %d after, want 2 after", len(before), len(after))
+	}
+}
+
+func TestDiskBlocks(t *testing.T) {
+	testBlockGC(t, &dagStore{blocks: &diskBlocks{t.TempDir()}})
+}
+
+// s3StandIn is just enough of an s3 bucket for s3Blocks, listing pageSize
+// objects per page.
+type s3StandIn struct {
+	mu       sync.Mutex
+	objects  map[string][]byte
+	pageSize int
+	pages    int // list pages served
+}
+
+func (b *s3StandIn) ServeHTTP(w http.ResponseWriter, req *http.Request) {
+	if !strings.HasPrefix(req.Header.Get("Authorization"), "AWS4-HMAC-SHA256 Credential=access/") {
+		http.Error(w, "unsigned", http.StatusForbidden)
+		return
+	}
+	b.mu.Lock()
+	defer b.mu.Unlock()
+
+	key := strings.TrimPrefix(req.URL.Path, "/bucket/")
+	switch {
+	case req.Method == "GET" && key == "":
+		query := req.URL.Query()
+		if query.Get("list-type") != "2" {
+			http.Error(w, "v1 listing", http.StatusBadRequest)
+			return
+		}
+		var keys []string
+		for k := range b.objects {
+			if strings.HasPrefix(k, query.Get("prefix")) {
+				keys = append(keys, k)
+			}
+		}
+		sort.Strings(keys)
+		start, _ := strconv.Atoi(query.Get("continuation-token"))
+		end := start + b.pageSize
+		type object struct{ Key string }
+		list := struct {
+			XMLName               xml.Name `xml:"ListBucketResult"`
+			Contents              []object
+			IsTruncated           bool
+			NextContinuationToken string `xml:",omitempty"`
+		}{}
+		if end < len(keys) {
+			list.IsTruncated, list.NextContinuationToken = true, strconv.Itoa(end)
+		} else {
+			end = len(keys)
+		}
+		for _, k := range keys[start:end] {
+			list.Contents = append(list.Contents, object{k})
+		}
+		b.pages++
+		xml.NewEncoder(w).Encode(list)
+	case req.Method == "GET":
+		data, ok := b.objects[key]
+		if !ok {
+			http.Error(w, "NoSuchKey", http.StatusNotFound)
+			return
+		}
+		w.Write(data)
+	case req.Method == "PUT":
+		data, _ := ioutil.ReadAll(req.Body)
+		b.objects[key] = data
+	case req.Method == "DELETE":
+		delete(b.objects, key)
+		w.WriteHeader(http.StatusNoContent)
+	default:
+		http.Error(w, "bad method", http.StatusMethodNotAllowed)
+	}
+}
+
+func TestS3Blocks(t *testing.T) {
+	bucket := &s3StandIn{objects: make(map[string][]byte), pageSize: 2}
+	srv := httptest.NewServer(bucket)
+	defer srv.Close()
+
+	conf := s3Config{Endpoint: srv.URL, Bucket: "bucket", Region: "us-east-1", AccessKey: "access", SecretKey: "secret"}
+	blocks := &s3Blocks{conf, srv.Client()}
+	if _, err := blocks.Get("nope"); !os.IsNotExist(err) {
+		t.Fatalf("missing block: got %v, want not exist", err)
+	}
+	testBlockGC(t, &dagStore{blocks: blocks})
+	if bucket.pages < 2 {
+		t.Fatalf("listed %d pages, want the continuation token followed", bucket.pages)
+	}
+	for k := range bucket.objects {
+		if !strings.HasPrefix(k, "blocks/") {
+			t.Fatalf("object %q outside blocks/", k)
+		}
+	}
+}
+
+func TestCachedStore(t *testing.T) {
+	c := newCachedStore(newMemStore(), t.TempDir(), cacheSize)
+	key, err := c.Add(strings.NewReader(testPaste))
+	if err != nil {
+		t.Fatal(err)
+	}
+
+	hits, misses := cacheHits.Value(), cacheMisses.Value()
+	for i := 0; i < 2; i++ {
+		r, err := c.Cat(key, 4, 5)
+		if err != nil {
+			t.Fatal(err)
+		}
+		got, _ := ioutil.ReadAll(r)
+		r.Close()
+		if string(got) != testPaste[4:9] {
+			t.Fatalf("read %d: got %q", i, got)
+		}
+	}
+	if cacheHits.Value()-hits != 1 || cacheMisses.Value()-misses != 1 {
+		t.Fatalf("got %d hits %d misses, want 1 of each", cacheHits.Value()-hits, cacheMisses.Value()-misses)
+	}
+}
+
+// cacheHolds reports whether any file under the cache dir contains s.
+func cacheHolds(t *testing.T, dir, s string) bool {
+	t.Helper()
+	found := false
+	err := filepath.Walk(dir, func(p string, info os.FileInfo, err error) error {
+		if err != nil || info.IsDir() {
+			return err
+		}
+		b, err := ioutil.ReadFile(p)
+		found = found || bytes.Contains(b, []byte(s))
+		return err
+	})
+	if err != nil {
+		t.Fatal(err)
+	}
+	return found
+}
+
+func TestCacheEviction(t *testing.T) {
+	dir := t.TempDir()
+	s, idx := newCachedStore(newMemStore(), dir, cacheSize), newPasteIndex(t.TempDir())
+	srv := httptest.NewServer(newHandler(s, idx, newShortIDs(t.TempDir()), newNameIndex(t.TempDir()), false))
+	defer srv.Close()
+
+	resp, body := doRequest(t, "PUT", srv.URL+"/notes.txt?views=2", strings.NewReader(testPaste), nil)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("put: %s %q", resp.Status, body)
+	}
+	pasteURL := strings.TrimSpace(body)
+	doRequest(t, "GET", pasteURL, nil, nil)
+	if !cacheHolds(t, dir, "quick brown fox") {
+		t.Fatal("first view wasn't cached")
+	}
+	if resp, body = doRequest(t, "GET", pasteURL, nil, nil); body != testPaste {
+		t.Fatalf("last view: %s %q", resp.Status, body)
+	}
+	if cacheHolds(t, dir, "quick brown fox") {
+		t.Fatal("still cached after the last view")
+	}
+
+	resp, body = doRequest(t, "PUT", srv.URL+"/notes.txt?json", strings.NewReader(testPaste), nil)
+	var result uploadResult
+	if err := json.Unmarshal([]byte(body), &result); err != nil {
+		t.Fatalf("put: %s %q (%s)", resp.Status, body, err)
+	}
+	doRequest(t, "GET", result.URL, nil, nil)
+	if !cacheHolds(t, dir, "quick brown fox") {
+		t.Fatal("read wasn't cached")
+	}
+	if resp, _ = doRequest(t, "DELETE", result.URL, nil, http.Header{"X-Delete-Token": {result.DeleteToken}}); resp.StatusCode != http.StatusOK {
+		t.Fatalf("delete: got %s, want 200", resp.Status)
+	}
+	if cacheHolds(t, dir, "quick brown fox") {
+		t.Fatal("still cached after delete")
+	}
+}
+
+func TestPutAndRead(t *testing.T) {
+	srv := newTestServer(t)
+
+	resp, body := doRequest(t, "PUT", srv.URL+"/", strings.NewReader(testPaste), nil)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("put: %s %s", resp.Status, body)
+	}
+	pasteURL, hash := strings.TrimSpace(body), pasteHash(resp)
+	if len(pasteURL) != len(srv.URL)+1+urlLength || !strings.HasPrefix(hash, "Qm") {
+		t.Fatalf("unexpected paste url %q for %q", pasteURL, hash)
+	}
+
+	for _, u := range []string{pasteURL, srv.URL + "/" + hash} {
+		resp, body = doRequest(t, "GET", u, nil, nil)
+		if resp.StatusCode != http.StatusOK || body != testPaste {
+			t.Fatalf("read %s: %s %q", u, resp.Status, body)
+		}
+	}
+
+	// the same paste gets the same short url
+	resp, body = doRequest(t, "PUT", srv.URL+"/", strings.NewReader(testPaste), nil)
+	if strings.TrimSpace(body) != pasteURL {
+		t.Fatalf("reupload got %q, want %q", body, pasteURL)
+	}
+}
+
+func TestReadRangeAndConditional(t *testing.T) {
+	srv := newTestServer(t)
+
+	_, body := doRequest(t, "PUT", srv.URL+"/", strings.NewReader(testPaste), nil)
+	pasteURL := strings.TrimSpace(body)
+
+	resp, body := doRequest(t, "GET", pasteURL, nil, http.Header{"Range": {"bytes=4-8"}})
+	if resp.StatusCode != http.StatusPartialContent || body != testPaste[4:9] {
+		t.Fatalf("range: %s %q", resp.Status, body)
+	}
+
+	resp, body = doRequest(t, "HEAD", pasteURL, nil, nil)
+	etag := resp.Header.Get("ETag")
+	if resp.StatusCode != http.StatusOK || body != "" || etag == "" {
+		t.Fatalf("head: %s %q etag %q", resp.Status, body, etag)
+	}
+	if !strings.Contains(resp.Header.Get("Cache-Control"), "immutable") {
+		t.Fatalf("head: cache-control %q", resp.Header.Get("Cache-Control"))
+	}
+
+	resp, _ = doRequest(t, "GET", pasteURL, nil, http.Header{"If-None-Match": {etag}})
+	if resp.StatusCode != http.StatusNotModified {
+		t.Fatalf("if-none-match: got %s, want 304", resp.Status)
+	}
+}
+
+func TestPutNamed(t *testing.T) {
+	srv := newTestServer(t)
+
+	resp, body := doRequest(t, "PUT", srv.URL+"/notes.txt", strings.NewReader(testPaste), nil)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("put: %s %s", resp.Status, body)
+	}
+	pasteURL := strings.TrimSpace(body)
+	if !strings.HasSuffix(pasteURL, "/notes.txt") {
+		t.Fatalf("paste url %q lost the filename", pasteURL)
+	}
+
+	resp, body = doRequest(t, "GET", pasteURL, nil, nil)
+	if resp.StatusCode != http.StatusOK || body != testPaste {
+		t.Fatalf("read: %s %q", resp.Status, body)
+	}
+
+	resp, _ = doRequest(t, "GET", strings.TrimSuffix(pasteURL, "notes.txt")+"other.txt", nil, nil)
+	if resp.StatusCode != http.StatusNotFound {
+		t.Fatalf("missing file in dir: got %s, want 404", resp.Status)
+	}
+}
+
+func TestMultiFileUpload(t *testing.T) {
+	srv := newTestServer(t)
+	files := map[string]string{"a.txt": testPaste, "b.txt": strings.ToUpper(testPaste)}
+
+	var form bytes.Buffer
+	mw := multipart.NewWriter(&form)
+	for name, content := range files {
+		fw, err := mw.CreateFormFile("f", name)
+		if err != nil {
+			t.Fatal(err)
+		}
+		fw.Write([]byte(content))
+	}
+	mw.Close()
+	header := http.Header{"Content-Type": {mw.FormDataContentType()}}
+	resp, body := doRequest(t, "POST", srv.URL+"/", &form, header)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("post: %s %s", resp.Status, body)
+	}
+	dirURL := strings.TrimSpace(body)
+
+	for name, content := range files {
+		resp, body = doRequest(t, "GET", dirURL+"/"+name, nil, nil)
+		if resp.StatusCode != http.StatusOK || body != content {
+			t.Fatalf("read %s: %s %q", name, resp.Status, body)
+		}
+	}
+}
+
+// sparseTar is a pax tar holding one sparse file of size bytes that's all
+// hole, so it's a couple of KB whatever size says.
+func sparseTar(name string, size int64) *bytes.Buffer {
+	block := func(name string, typeflag byte, size int) []byte {
+		b := make([]byte, 512)
+		copy(b, name)
+		copy(b[100:], "0000644\x00")
+		copy(b[108:], "0000000\x00")
+		copy(b[116:], "0000000\x00")
+		copy(b[124:], fmt.Sprintf("%011o\x00", size))
+		copy(b[136:], "00000000000\x00")
+		b[156] = typeflag
+		copy(b[257:], "ustar\x0000")
+		copy(b[148:], "        ")
+		sum := 0
+		for _, c := range b {
+			sum += int(c)
+		}
+		copy(b[148:], fmt.Sprintf("%06o\x00 ", sum))
+		return b
+	}
+	pad := func(b []byte) []byte {
+		return append(b, make([]byte, (512-len(b)%512)%512)...)
+	}
+	var pax []byte
+	for _, kv := range [][2]string{
+		{"GNU.sparse.major", "1"},
+		{"GNU.sparse.minor", "0"},
+		{"GNU.sparse.name", name},
+		{"GNU.sparse.realsize", strconv.FormatInt(size, 10)},
+	} {
+		rec := " " + kv[0] + "=" + kv[1] + "\n"
+		n := len(rec) + 1
+		for len(strconv.Itoa(n))+len(rec) != n {
+			n = len(strconv.Itoa(n)) + len(rec)
+		}
+		pax = append(pax, strconv.Itoa(n)+rec...)
+	}
+	// the sparse map: one empty fragment, the rest is hole
+	sparseMap := pad([]byte("1\n0\n0\n"))
+
+	var buf bytes.Buffer
+	buf.Write(block("pax", 'x', len(pax)))
+	buf.Write(pad(pax))
+	buf.Write(block("GNUSparseFile.0/"+name, '0', len(sparseMap)))
+	buf.Write(sparseMap)
+	buf.Write(make([]byte, 1024))
+	return &buf
+}
+
+func TestTarUpload(t *testing.T) {
+	srv, s, _ := newTestServerWith(t)
+
+	tarball := func(names ...string) *bytes.Buffer {
+		var buf bytes.Buffer
+		tw := tar.NewWriter(&buf)
+		for _, name := range names {
+			tw.WriteHeader(&tar.Header{Name: name, Mode: 0644, Size: int64(len(testPaste)), Typeflag: tar.TypeReg})
+			tw.Write([]byte(testPaste))
+		}
+		tw.Close()
+		return &buf
+	}
+
+	resp, body := doRequest(t, "PUT", srv.URL+"/?tar", tarball("top.txt", "sub/inner.txt"), nil)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("put: %s %s", resp.Status, body)
+	}
+	dirURL, hash := strings.TrimSpace(body), pasteHash(resp)
+
+	resp, body = doRequest(t, "GET", dirURL+"/top.txt", nil, nil)
+	if resp.StatusCode != http.StatusOK || body != testPaste {
+		t.Fatalf("read top.txt: %s %q", resp.Status, body)
+	}
+	if e, err := s.Stat(hash + "/sub/inner.txt"); err != nil || e.Size != int64(len(testPaste)) {
+		t.Fatalf("stat sub/inner.txt: %+v %v", e, err)
+	}
+
+	header := http.Header{"Content-Type": {"application/x-tar"}}
+	resp, _ = doRequest(t, "PUT", srv.URL+"/", tarball("../escape.txt"), header)
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("path traversal: got %s, want 400", resp.Status)
+	}
+	resp, _ = doRequest(t, "PUT", srv.URL+"/?tar", sparseTar("hole.bin", 4<<30), nil)
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("sparse member: got %s, want 400", resp.Status)
+	}
+}
+
+func TestDirListing(t *testing.T) {
+	srv := newTestServer(t)
+
+	var buf bytes.Buffer
+	tw := tar.NewWriter(&buf)
+	for _, name := range []string{"top.txt", "sub dir/inner.txt"} {
+		tw.WriteHeader(&tar.Header{Name: name, Mode: 0644, Size: int64(len(testPaste)), Typeflag: tar.TypeReg})
+		tw.Write([]byte(testPaste))
+	}
+	tw.Close()
+	resp, body := doRequest(t, "PUT", srv.URL+"/?tar", &buf, nil)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("put: %s %s", resp.Status, body)
+	}
+	dirURL := strings.TrimSpace(body)
+
+	resp, body = doRequest(t, "GET", dirURL, nil, nil)
+	if resp.StatusCode != http.StatusOK || !strings.Contains(body, "top.txt") || !strings.Contains(body, "sub%20dir/") {
+		t.Fatalf("text listing: %s %q", resp.Status, body)
+	}
+
+	resp, body = doRequest(t, "GET", dirURL+"/sub%20dir/?json", nil, nil)
+	var listing struct {
+		Entries []listEntry `json:"entries"`
+	}
+	if err := json.Unmarshal([]byte(body), &listing); err != nil || len(listing.Entries) != 1 {
+		t.Fatalf("json listing: %s %q %v", resp.Status, body, err)
+	}
+	if e := listing.Entries[0]; e.Name != "inner.txt" || e.IsDir || e.Size != int64(len(testPaste)) {
+		t.Fatalf("json listing entry: %+v", e)
+	}
+
+	resp, body = doRequest(t, "GET", srv.URL+listing.Entries[0].URL, nil, nil)
+	if resp.StatusCode != http.StatusOK || body != testPaste {
+		t.Fatalf("read nested: %s %q", resp.Status, body)
+	}
+
+	resp, body = doRequest(t, "GET", dirURL, nil, http.Header{"Accept": {"text/html"}})
+	if !strings.Contains(resp.Header.Get("Content-Type"), "text/html") || !strings.Contains(body, `href="`) {
+		t.Fatalf("html listing: %s %q", resp.Header.Get("Content-Type"), body)
+	}
+}
+
+func TestDirArchive(t *testing.T) {
+	srv := newTestServer(t)
+	files := map[string]string{"a.txt": testPaste, "sub/b.txt": strings.ToUpper(testPaste)}
+
+	var buf bytes.Buffer
+	tw := tar.NewWriter(&buf)
+	for name, content := range files {
+		tw.WriteHeader(&tar.Header{Name: name, Mode: 0644, Size: int64(len(content)), Typeflag: tar.TypeReg})
+		tw.Write([]byte(content))
+	}
+	tw.Close()
+	resp, body := doRequest(t, "PUT", srv.URL+"/?tar", &buf, nil)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("put: %s %s", resp.Status, body)
+	}
+	dirURL, hash := strings.TrimSpace(body), pasteHash(resp)
+
+	resp, body = doRequest(t, "GET", dirURL+"?tar", nil, nil)
+	if cd := resp.Header.Get("Content-Disposition"); cd != fmt.Sprintf("attachment; filename=%s.tar", hash) {
+		t.Fatalf("tar disposition: %q", cd)
+	}
+	got := map[string]string{}
+	tr := tar.NewReader(strings.NewReader(body))
+	for {
+		hdr, err := tr.Next()
+		if err == io.EOF {
+			break
+		} else if err != nil {
+			t.Fatal(err)
+		}
+		if hdr.Typeflag == tar.TypeReg {
+			b, _ := ioutil.ReadAll(tr)
+			got[strings.TrimPrefix(hdr.Name, hash+"/")] = string(b)
+		}
+	}
+	if fmt.Sprint(got) != fmt.Sprint(files) {
+		t.Fatalf("tar contents: %v", got)
+	}
+
+	resp, body = doRequest(t, "GET", dirURL+"?zip", nil, nil)
+	zr, err := zip.NewReader(strings.NewReader(body), int64(len(body)))
+	if err != nil {
+		t.Fatalf("zip: %s %v", resp.Status, err)
+	}
+	got = map[string]string{}
+	for _, f := range zr.File {
+		if strings.HasSuffix(f.Name, "/") {
+			continue
+		}
+		r, _ := f.Open()
+		b, _ := ioutil.ReadAll(r)
+		got[strings.TrimPrefix(f.Name, hash+"/")] = string(b)
+	}
+	if fmt.Sprint(got) != fmt.Sprint(files) {
+		t.Fatalf("zip contents: %v", got)
+	}
+}
+
+func TestExtractUpload(t *testing.T) {
+	srv := newTestServer(t)
+
+	zipball := func(name string, size int) *bytes.Buffer {
+		var buf bytes.Buffer
+		zw := zip.NewWriter(&buf)
+		fw, _ := zw.Create(name)
+		fw.Write(bytes.Repeat([]byte("a"), size))
+		zw.Close()
+		return &buf
+	}
+
+	var gzBuf bytes.Buffer
+	gz := gzip.NewWriter(&gzBuf)
+	tw := tar.NewWriter(gz)
+	tw.WriteHeader(&tar.Header{Name: "project/main.go", Mode: 0644, Size: int64(len(testPaste)), Typeflag: tar.TypeReg})
+	tw.Write([]byte(testPaste))
+	tw.Close()
+	gz.Close()
+
+	for name, archive := range map[string]io.Reader{"tar.gz": &gzBuf, "zip": zipball("project/main.go", 64)} {
+		resp, body := doRequest(t, "PUT", srv.URL+"/?extract", archive, nil)
+		if resp.StatusCode != http.StatusOK {
+			t.Fatalf("%s: %s %s", name, resp.Status, body)
+		}
+		resp, body = doRequest(t, "GET", strings.TrimSpace(body)+"/project/main.go", nil, nil)
+		if resp.StatusCode != http.StatusOK || len(body) == 0 {
+			t.Fatalf("%s read: %s %q", name, resp.Status, body)
+		}
+	}
+
+	resp, _ := doRequest(t, "PUT", srv.URL+"/?extract", zipball("../escape.txt", 64), nil)
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("path traversal: got %s, want 400", resp.Status)
+	}
+	resp, _ = doRequest(t, "PUT", srv.URL+"/?extract", zipball("bomb.txt", maxPasteSize+1), nil)
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("zip bomb: got %s, want 400", resp.Status)
+	}
+	var sparse bytes.Buffer
+	gz = gzip.NewWriter(&sparse)
+	io.Copy(gz, sparseTar("hole.bin", maxPasteSize+1))
+	gz.Close()
+	resp, _ = doRequest(t, "PUT", srv.URL+"/?extract", &sparse, nil)
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("sparse tar bomb: got %s, want 400", resp.Status)
+	}
+}
+
+func TestBrowseArchive(t *testing.T) {
+	srv := newTestServer(t)
+
+	var zipBuf bytes.Buffer
+	zw := zip.NewWriter(&zipBuf)
+	fw, _ := zw.Create("src/main.go")
+	fw.Write([]byte(testPaste))
+	zw.Close()
+
+	var tgzBuf bytes.Buffer
+	gz := gzip.NewWriter(&tgzBuf)
+	tw := tar.NewWriter(gz)
+	tw.WriteHeader(&tar.Header{Name: "./src/main.go", Mode: 0644, Size: int64(len(testPaste)), Typeflag: tar.TypeReg})
+	tw.Write([]byte(testPaste))
+	tw.Close()
+	gz.Close()
+	tgz := tgzBuf.Bytes()
+
+	for name, archive := range map[string]io.Reader{"build.zip": &zipBuf, "logs.tar.gz": &tgzBuf} {
+		resp, body := doRequest(t, "PUT", srv.URL+"/"+name, archive, nil)
+		if resp.StatusCode != http.StatusOK {
+			t.Fatalf("put %s: %s %s", name, resp.Status, body)
+		}
+		pasteURL := strings.TrimSpace(body)
+
+		resp, body = doRequest(t, "GET", pasteURL+"?ls", nil, nil)
+		if resp.StatusCode != http.StatusOK || !strings.Contains(body, "/"+name+"/src/main.go") {
+			t.Fatalf("ls %s: %s %q", name, resp.Status, body)
+		}
+		resp, body = doRequest(t, "GET", pasteURL+"/src/main.go", nil, nil)
+		if resp.StatusCode != http.StatusOK || body != testPaste {
+			t.Fatalf("member of %s: %s %q", name, resp.Status, body)
+		}
+		resp, _ = doRequest(t, "GET", pasteURL+"/src/missing.go", nil, nil)
+		if resp.StatusCode != http.StatusNotFound {
+			t.Fatalf("missing member of %s: got %s, want 404", name, resp.Status)
+		}
+	}
+
+	// password protected archives would have to be decrypted whole
+	password := http.Header{"X-Password": {"hunter2"}}
+	resp, body := doRequest(t, "PUT", srv.URL+"/locked.tar.gz", bytes.NewReader(tgz), password)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("put locked.tar.gz: %s %s", resp.Status, body)
+	}
+	pasteURL := strings.TrimSpace(body)
+	if resp, _ = doRequest(t, "GET", pasteURL+"?ls", nil, nil); resp.StatusCode != http.StatusUnprocessableEntity {
+		t.Fatalf("ls of a password protected archive: got %s, want 422", resp.Status)
+	}
+	if resp, _ = doRequest(t, "GET", pasteURL+"/src/main.go", nil, nil); resp.StatusCode != http.StatusNotFound {
+		t.Fatalf("member of a password protected archive: got %s, want 404", resp.Status)
+	}
+}
+
+func TestContentType(t *testing.T) {
+	srv := newTestServer(t)
+	const page = "<html><script>alert(document.cookie)</script></html>"
+	png := "\x89PNG\r\n\x1a\n" + strings.Repeat("\x00", 32)
+
+	for _, c := range []struct{ path, body, want string }{
+		{"/", page, "text/plain; charset=utf-8"},
+		{"/page.html", page, "text/plain; charset=utf-8"},
+		{"/logo.svg", "<svg xmlns='http://www.w3.org/2000/svg'><script>alert(1)</script></svg>", "text/plain; charset=utf-8"},
+		{"/", png, "image/png"},
+		{"/notes.txt", testPaste, "text/plain; charset=utf-8"},
+		{"/feed.rdf", `<?xml-stylesheet type="text/xsl" href="#x"?><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"/>`, "text/plain; charset=utf-8"},
+		{"/", "\x00\x01\x02\x03" + testPaste, "application/octet-stream"},
+	} {
+		resp, body := doRequest(t, "PUT", srv.URL+c.path, strings.NewReader(c.body), nil)
+		if resp.StatusCode != http.StatusOK {
+			t.Fatalf("put %s: %s %s", c.path, resp.Status, body)
+		}
+		resp, _ = doRequest(t, "GET", strings.TrimSpace(body), nil, nil)
+		if ct := resp.Header.Get("Content-Type"); ct != c.want {
+			t.Errorf("%s: Content-Type %q, want %q", c.path, ct, c.want)
+		}
+		if resp.Header.Get("X-Content-Type-Options") != "nosniff" {
+			t.Errorf("%s: missing nosniff", c.path)
+		}
+		if attachment := strings.HasPrefix(resp.Header.Get("Content-Disposition"), "attachment"); attachment != (c.want == "application/octet-stream") {
+			t.Errorf("%s: Content-Disposition %q", c.path, resp.Header.Get("Content-Disposition"))
+		}
+	}
+}
+
+func TestPost(t *testing.T) {
+	srv := newTestServer(t)
+
+	form := url.Values{formVal: {testPaste}}
+	header := http.Header{"Content-Type": {"application/x-www-form-urlencoded"}}
+	resp, body := doRequest(t, "POST", srv.URL+"/", strings.NewReader(form.Encode()), header)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("post: %s %s", resp.Status, body)
+	}
+
+	resp, body = doRequest(t, "GET", strings.TrimSpace(body), nil, nil)
+	if resp.StatusCode != http.StatusOK || body != testPaste {
+		t.Fatalf("read: %s %q", resp.Status, body)
+	}
+}
+
+func TestPasteTooSmall(t *testing.T) {
+	srv := newTestServer(t)
+
+	resp, body := doRequest(t, "PUT", srv.URL+"/", strings.NewReader("tiny"), nil)
+	if resp.StatusCode != http.StatusNotAcceptable {
+		t.Fatalf("got %s %q, want 406", resp.Status, body)
+	}
+}
+
+func TestPasteTooLarge(t *testing.T) {
+	h := newHandler(newMemStore(), newPasteIndex(t.TempDir()), newShortIDs(t.TempDir()), newNameIndex(t.TempDir()), false)
+
+	// rejected up front from the announced length
+	req := httptest.NewRequest("PUT", "/", strings.NewReader(testPaste))
+	req.ContentLength = maxPasteSize + 1
+	rec := httptest.NewRecorder()
+	h.ServeHTTP(rec, req)
+	if rec.Code != http.StatusRequestEntityTooLarge {
+		t.Fatalf("content-length: got %d, want 413", rec.Code)
+	}
+
+	// rejected while streaming when the length isn't known
+	req = httptest.NewRequest("PUT", "/big.bin", bytes.NewReader(make([]byte, maxPasteSize+1)))
+	req.ContentLength = -1
+	rec = httptest.NewRecorder()
+	h.ServeHTTP(rec, req)
+	if rec.Code != http.StatusRequestEntityTooLarge {
+		t.Fatalf("streamed: got %d, want 413", rec.Code)
+	}
+
+	// urlencoded forms too, rather than parsing to an empty paste
+	form := url.Values{formVal: {strings.Repeat("a", maxPasteSize+1)}}
+	req = httptest.NewRequest("POST", "/", strings.NewReader(form.Encode()))
+	req.Header.Set("Content-Type", "application/x-www-form-urlencoded")
+	req.ContentLength = -1
+	rec = httptest.NewRecorder()
+	h.ServeHTTP(rec, req)
+	if rec.Code != http.StatusRequestEntityTooLarge {
+		t.Fatalf("urlencoded: got %d, want 413", rec.Code)
+	}
+
+	// sealing overhead doesn't count, only the paste as uploaded
+	id, err := age.GenerateX25519Identity()
+	if err != nil {
+		t.Fatal(err)
+	}
+	for _, query := range []string{"?encrypt", "?to=" + id.Recipient().String()} {
+		for _, c := range []struct {
+			size int
+			want int
+		}{{maxPasteSize, http.StatusOK}, {maxPasteSize + 1, http.StatusRequestEntityTooLarge}} {
+			req = httptest.NewRequest("PUT", "/"+query, bytes.NewReader(make([]byte, c.size)))
+			req.ContentLength = -1
+			rec = httptest.NewRecorder()
+			h.ServeHTTP(rec, req)
+			if rec.Code != c.want {
+				t.Fatalf("%s with %d bytes: got %d %q, want %d", query, c.size, rec.Code, rec.Body.String(), c.want)
+			}
+		}
+	}
+}
+
+func TestExpiry(t *testing.T) {
+	srv, s, idx := newTestServerWith(t)
+
+	resp, body := doRequest(t, "PUT", srv.URL+"/?expires=1d", strings.NewReader(testPaste), nil)
+	if resp.StatusCode != http.StatusOK || resp.Header.Get("X-Expires") == "" {
+		t.Fatalf("put: %s %q expires %q", resp.Status, body, resp.Header.Get("X-Expires"))
+	}
+	pasteURL, hash := strings.TrimSpace(body), pasteHash(resp)
+
+	// pretend a day went by
+	idx.update(hash, func(m *pasteMeta, ok bool) error {
+		m.Expires = time.Now().Add(-time.Second)
+		return nil
+	})
+	resp, _ = doRequest(t, "GET", pasteURL, nil, nil)
+	if resp.StatusCode != http.StatusGone {
+		t.Fatalf("read after expiry: got %s, want 410", resp.Status)
+	}
+
+	reap(s, idx)
+	if _, err := s.Stat(hash); err == nil {
+		t.Fatal("expired paste still in the store after reaping")
+	}
+	if m, _ := idx.get(hash); !m.Removed {
+		t.Fatal("expired paste not marked removed")
+	}
+
+	resp, _ = doRequest(t, "PUT", srv.URL+"/", strings.NewReader(testPaste), http.Header{"X-Expires": {"soon"}})
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("bad expiry: got %s, want 400", resp.Status)
+	}
+}
+
+func TestViewLimits(t *testing.T) {
+	srv, s, idx := newTestServerWith(t)
+
+	for _, c := range []struct {
+		query string
+		views int
+	}{{"?burn", 1}, {"?views=3", 3}} {
+		paste := fmt.Sprintf("%s%s", testPaste, c.query)
+		resp, body := doRequest(t, "PUT", srv.URL+"/"+c.query, strings.NewReader(paste), nil)
+		if resp.StatusCode != http.StatusOK {
+			t.Fatalf("%s put: %s %q", c.query, resp.Status, body)
+		}
+		pasteURL, hash := strings.TrimSpace(body), pasteHash(resp)
+
+		// HEAD doesn't use up a view
+		doRequest(t, "HEAD", pasteURL, nil, nil)
+		for i := 0; i < c.views; i++ {
+			resp, body = doRequest(t, "GET", pasteURL, nil, nil)
+			if resp.StatusCode != http.StatusOK || body != paste {
+				t.Fatalf("%s view %d: %s %q", c.query, i, resp.Status, body)
+			}
+		}
+		resp, _ = doRequest(t, "GET", pasteURL, nil, nil)
+		if resp.StatusCode != http.StatusGone {
+			t.Fatalf("%s: read past the view limit got %s, want 410", c.query, resp.Status)
+		}
+
+		reap(s, idx)
+		if m, _ := idx.get(hash); !m.Removed {
+			t.Fatalf("%s: not unpinned after the last view", c.query)
+		}
+		if _, err := s.Stat(hash); err == nil {
+			t.Fatalf("%s: still in the store after gc", c.query)
+		}
+	}
+}
+
+func TestViewLimitConflict(t *testing.T) {
+	srv := newTestServer(t)
+
+	resp, body := doRequest(t, "PUT", srv.URL+"/", strings.NewReader(testPaste), nil)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("put: %s %q", resp.Status, body)
+	}
+	pasteURL := strings.TrimSpace(body)
+
+	// the same bytes with a view limit would share the unlimited paste
+	resp, body = doRequest(t, "PUT", srv.URL+"/?burn", strings.NewReader(testPaste), nil)
+	if resp.StatusCode != http.StatusConflict {
+		t.Fatalf("put ?burn of a live paste: got %s %q, want 409", resp.Status, body)
+	}
+	for i := 0; i < 2; i++ {
+		if resp, body = doRequest(t, "GET", pasteURL, nil, nil); resp.StatusCode != http.StatusOK || body != testPaste {
+			t.Fatalf("read %d: %s %q", i, resp.Status, body)
+		}
+	}
+}
+
+func TestDelete(t *testing.T) {
+	srv := newTestServer(t)
+
+	resp, body := doRequest(t, "PUT", srv.URL+"/notes.txt?json", strings.NewReader(testPaste), nil)
+	var result uploadResult
+	if err := json.Unmarshal([]byte(body), &result); err != nil {
+		t.Fatalf("put: %s %q (%s)", resp.Status, body, err)
+	}
+	if result.DeleteToken == "" || resp.Header.Get("X-Delete-Token") != result.DeleteToken {
+		t.Fatalf("put: no deletion token in %q", body)
+	}
+
+	resp, _ = doRequest(t, "DELETE", result.URL, nil, http.Header{"X-Delete-Token": {"nope"}})
+	if resp.StatusCode != http.StatusForbidden {
+		t.Fatalf("delete with a bad token: got %s, want 403", resp.Status)
+	}
+	resp, _ = doRequest(t, "GET", result.URL, nil, nil)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("read after failed delete: got %s, want 200", resp.Status)
+	}
+
+	resp, _ = doRequest(t, "DELETE", result.URL+"?token="+result.DeleteToken, nil, nil)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("delete: got %s, want 200", resp.Status)
+	}
+	resp, _ = doRequest(t, "GET", result.URL, nil, nil)
+	if resp.StatusCode != http.StatusGone {
+		t.Fatalf("read after delete: got %s, want 410", resp.Status)
+	}
+}
+
+func TestDeleteShared(t *testing.T) {
+	srv := newTestServer(t)
+
+	var results [2]uploadResult
+	for i := range results {
+		resp, body := doRequest(t, "PUT", srv.URL+"/?json", strings.NewReader(testPaste), nil)
+		if err := json.Unmarshal([]byte(body), &results[i]); err != nil {
+			t.Fatalf("put %d: %s %q (%s)", i, resp.Status, body, err)
+		}
+	}
+	if results[0].URL != results[1].URL {
+		t.Fatalf("identical uploads got %q and %q", results[0].URL, results[1].URL)
+	}
+
+	// the first uploader deleting leaves the second one's paste up
+	resp, _ := doRequest(t, "DELETE", results[0].URL, nil, http.Header{"X-Delete-Token": {results[0].DeleteToken}})
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("first delete: got %s, want 200", resp.Status)
+	}
+	if resp, body := doRequest(t, "GET", results[1].URL, nil, nil); resp.StatusCode != http.StatusOK || body != testPaste {
+		t.Fatalf("read after first delete: %s %q", resp.Status, body)
+	}
+	resp, _ = doRequest(t, "DELETE", results[0].URL, nil, http.Header{"X-Delete-Token": {results[0].DeleteToken}})
+	if resp.StatusCode != http.StatusForbidden {
+		t.Fatalf("reused token: got %s, want 403", resp.Status)
+	}
+
+	resp, _ = doRequest(t, "DELETE", results[1].URL, nil, http.Header{"X-Delete-Token": {results[1].DeleteToken}})
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("last delete: got %s, want 200", resp.Status)
+	}
+	if resp, _ = doRequest(t, "GET", results[1].URL, nil, nil); resp.StatusCode != http.StatusGone {
+		t.Fatalf("read after last delete: got %s, want 410", resp.Status)
+	}
+}
+
+func TestPasteExpiryLimits(t *testing.T) {
+	year := 365 * 24 * time.Hour
+	for _, c := range []struct {
+		requested time.Duration
+		size      int64
+		want      time.Duration
+	}{
+		{0, 100, 0},
+		{time.Hour, 100, time.Hour},
+		{0, 2 * 1024 * 1024, year},
+		{2 * year, 2 * 1024 * 1024, year},
+		{time.Hour, maxPasteSize, time.Hour},
+	} {
+		if got := pasteExpiry(c.requested, c.size); got != c.want {
+			t.Errorf("pasteExpiry(%v, %d) = %v, want %v", c.requested, c.size, got, c.want)
+		}
+	}
+}
+
+func TestShortIDsGrow(t *testing.T) {
+	ids := newShortIDs(t.TempDir())
+	if l := ids.length(); l != urlLength {
+		t.Fatalf("empty index length %d, want %d", l, urlLength)
+	}
+	ids.n = int(math.Pow(float64(len(urlCharset)), urlLength)/16) + 1
+	id, err := ids.add("QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o")
+	if err != nil || len(id) != urlLength+1 {
+		t.Fatalf("add to a filling index: %q %v", id, err)
+	}
+	if hash, ok := ids.get(id); !ok || hash != "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o" {
+		t.Fatalf("get %q: %q %v", id, hash, ok)
+	}
+}
+
+func TestSlugs(t *testing.T) {
+	srv := newTestServer(t)
+
+	resp, body := doRequest(t, "PUT", srv.URL+"/s/deploy-notes", strings.NewReader(testPaste), nil)
+	if resp.StatusCode != http.StatusOK || strings.TrimSpace(body) != srv.URL+"/deploy-notes" {
+		t.Fatalf("claim: %s %q", resp.Status, body)
+	}
+	owner := resp.Header.Get("X-Delete-Token")
+	resp, body = doRequest(t, "GET", srv.URL+"/deploy-notes", nil, nil)
+	if resp.StatusCode != http.StatusOK || body != testPaste || resp.Header.Get("Cache-Control") != "no-cache" {
+		t.Fatalf("read: %s %q cache %q", resp.Status, body, resp.Header.Get("Cache-Control"))
+	}
+
+	update := strings.ToUpper(testPaste)
+	resp, _ = doRequest(t, "PUT", srv.URL+"/", strings.NewReader(update), http.Header{"X-Slug": {"deploy-notes"}})
+	if resp.StatusCode != http.StatusConflict {
+		t.Fatalf("claim taken slug: got %s, want 409", resp.Status)
+	}
+	resp, _ = doRequest(t, "PUT", srv.URL+"/s/deploy-notes", strings.NewReader(update), http.Header{"X-Delete-Token": {owner}})
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("repoint: %s", resp.Status)
+	}
+	if _, body = doRequest(t, "GET", srv.URL+"/deploy-notes", nil, nil); body != update {
+		t.Fatalf("read after repoint: %q", body)
+	}
+
+	for _, slug := range []string{"ipns", "no", "not/ok", "dots.txt"} {
+		resp, _ = doRequest(t, "PUT", srv.URL+"/?slug="+url.QueryEscape(slug), strings.NewReader(testPaste), nil)
+		if resp.StatusCode != http.StatusBadRequest {
+			t.Fatalf("slug %q: got %s, want 400", slug, resp.Status)
+		}
+	}
+}
+
+func TestMutableNames(t *testing.T) {
+	srv := newTestServer(t)
+
+	resp, body := doRequest(t, "PUT", srv.URL+"/?ipns", strings.NewReader(testPaste), nil)
+	nameURL, updateToken := strings.TrimSpace(body), resp.Header.Get("X-Update-Token")
+	if resp.StatusCode != http.StatusOK || !strings.HasPrefix(nameURL, srv.URL+"/ipns/") || updateToken == "" {
+		t.Fatalf("publish: %s %q token %q", resp.Status, body, updateToken)
+	}
+	resp, body = doRequest(t, "GET", nameURL, nil, nil)
+	if resp.StatusCode != http.StatusOK || body != testPaste || resp.Header.Get("Cache-Control") != "no-cache" {
+		t.Fatalf("read: %s %q cache %q", resp.Status, body, resp.Header.Get("Cache-Control"))
+	}
+
+	update := strings.ToUpper(testPaste)
+	resp, _ = doRequest(t, "PUT", nameURL, strings.NewReader(update), http.Header{"X-Update-Token": {"nope"}})
+	if resp.StatusCode != http.StatusForbidden {
+		t.Fatalf("update with a bad token: got %s, want 403", resp.Status)
+	}
+	resp, body = doRequest(t, "PUT", nameURL, strings.NewReader(update), http.Header{"X-Update-Token": {updateToken}})
+	if resp.StatusCode != http.StatusOK || strings.TrimSpace(body) != nameURL {
+		t.Fatalf("update: %s %q", resp.Status, body)
+	}
+	if _, body = doRequest(t, "GET", nameURL, nil, nil); body != update {
+		t.Fatalf("read after update: %q", body)
+	}
+
+	resp, body = doRequest(t, "GET", nameURL+"?history&json", nil, nil)
+	var history struct {
+		Revisions []nameRevision `json:"revisions"`
+	}
+	if err := json.Unmarshal([]byte(body), &history); err != nil || len(history.Revisions) != 2 {
+		t.Fatalf("history: %s %q %v", resp.Status, body, err)
+	}
+	resp, body = doRequest(t, "GET", nameURL+"?diff="+history.Revisions[1].Hash, nil, nil)
+	if resp.StatusCode != http.StatusOK || !strings.Contains(body, "-"+testPaste) || !strings.Contains(body, "+"+update) {
+		t.Fatalf("diff against the first revision: %s %q", resp.Status, body)
+	}
+
+	resp, _ = doRequest(t, "GET", srv.URL+"/ipns/kUnknownName", nil, nil)
+	if resp.StatusCode != http.StatusNotFound {
+		t.Fatalf("unknown name: got %s, want 404", resp.Status)
+	}
+}
+
+func TestUnifiedDiff(t *testing.T) {
+	a := "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n"
+	b := strings.Replace(strings.Replace(a, "two", "2", 1), "ten\n", "ten", 1)
+	want := `--- a
++++ b
+@@ -1,5 +1,5 @@
+ one
+-two
++2
+ three
+ four
+ five
+@@ -7,4 +7,4 @@
+ seven
+ eight
+ nine
+-ten
++ten
+\ No newline at end of file
+`
+	if got := unifiedDiff("a", "b", diffLines(a, b, false)); got != want {
+		t.Fatalf("got\n%s\nwant\n%s", got, want)
+	}
+	if got := unifiedDiff("a", "b", diffLines(a, a, false)); got != "" {
+		t.Fatalf("identical: got %q", got)
+	}
+}
+
+func TestDiffPastes(t *testing.T) {
+	srv := newTestServer(t)
+	defer srv.Close()
+
+	a := "func main() {\n\tprintln(1)\n}\n"
+	b := "func main()  {\n    println(2)\n}\n"
+	var urls [2]string
+	for i, paste := range []string{a, b} {
+		resp, body := doRequest(t, "PUT", srv.URL+"/", strings.NewReader(paste), nil)
+		if resp.StatusCode != http.StatusOK {
+			t.Fatalf("put: %s %q", resp.Status, body)
+		}
+		urls[i] = strings.TrimPrefix(strings.TrimSpace(body), srv.URL+"/")
+	}
+
+	resp, body := doRequest(t, "GET", srv.URL+"/diff/"+urls[0]+"/"+urls[1], nil, nil)
+	if resp.StatusCode != http.StatusOK || !strings.Contains(body, "\n-func main() {\n") || !strings.Contains(body, "\n+func main()  {\n") {
+		t.Fatalf("diff: %s %q", resp.Status, body)
+	}
+	resp, body = doRequest(t, "GET", srv.URL+"/diff/"+urls[0]+"/"+urls[1]+"?w", nil, nil)
+	if resp.StatusCode != http.StatusOK || !strings.Contains(body, "\n-\tprintln(1)\n+    println(2)\n") || strings.Contains(body, "-func") {
+		t.Fatalf("diff ignoring whitespace: %s %q", resp.Status, body)
+	}
+	resp, body = doRequest(t, "GET", srv.URL+"/diff/"+urls[0]+"/"+urls[1], nil, http.Header{"Accept": {"text/html"}})
+	if resp.StatusCode != http.StatusOK || !strings.Contains(body, `<span class="x">1</span>`) || !strings.Contains(body, `<span class="x">2</span>`) {
+		t.Fatalf("side by side diff: %s %q", resp.Status, body)
+	}
+	resp, _ = doRequest(t, "GET", srv.URL+"/diff/"+urls[0]+"/nope", nil, nil)
+	if resp.StatusCode != http.StatusNotFound {
+		t.Fatalf("diff against a missing paste: got %s, want 404", resp.Status)
+	}
+
+	resp, body = doRequest(t, "PUT", srv.URL+"/?burn", strings.NewReader(testPaste), nil)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("put ?burn: %s %q", resp.Status, body)
+	}
+	burnURL := strings.TrimSpace(body)
+	burn := strings.TrimPrefix(burnURL, srv.URL+"/")
+	for _, diffURL := range []string{
+		srv.URL + "/" + urls[0] + "?diff=" + burn,
+		srv.URL + "/diff/" + burn + "/" + urls[0],
+		burnURL + "?diff=" + urls[0],
+	} {
+		resp, body = doRequest(t, "GET", diffURL, nil, nil)
+		if resp.StatusCode != http.StatusForbidden || strings.Contains(body, "quick brown fox") {
+			t.Fatalf("%s: got %s %q, want 403", diffURL, resp.Status, body)
+		}
+	}
+	// the refused diffs didn't use up the view
+	if resp, body = doRequest(t, "GET", burnURL, nil, nil); resp.StatusCode != http.StatusOK || body != testPaste {
+		t.Fatalf("read after diffs: %s %q", resp.Status, body)
+	}
+}
+
+func TestEncryptedPaste(t *testing.T) {
+	srv, s, _ := newTestServerWith(t)
+
+	resp, body := doRequest(t, "PUT", srv.URL+"/notes.txt?encrypt", strings.NewReader(testPaste), nil)
+	pasteURL := strings.TrimSpace(body)
+	if resp.StatusCode != http.StatusOK || !strings.Contains(pasteURL, "/notes.txt?key=") {
+		t.Fatalf("put: %s %q", resp.Status, body)
+	}
+	stored, err := readPaste(s, strings.TrimPrefix(resp.Header.Get("X-Ipfs-Path"), "/ipfs/"))
+	if err != nil || bytes.Contains(stored, []byte("quick brown fox")) {
+		t.Fatalf("stored paste is not encrypted: %q %v", stored, err)
+	}
+
+	resp, body = doRequest(t, "GET", pasteURL, nil, nil)
+	if resp.StatusCode != http.StatusOK || body != testPaste || resp.Header.Get("Cache-Control") != "private, no-store" {
+		t.Fatalf("read: %s %q cache %q", resp.Status, body, resp.Header.Get("Cache-Control"))
+	}
+	resp, body = doRequest(t, "GET", pasteURL+"&md", nil, nil)
+	if resp.StatusCode != http.StatusOK || !strings.Contains(body, "quick brown fox") {
+		t.Fatalf("render: %s %q", resp.Status, body)
+	}
+
+	plainURL := strings.SplitN(pasteURL, "?", 2)[0]
+	otherKey, _ := newSecret()
+	for _, u := range []string{plainURL, plainURL + "?key=" + otherKey, plainURL + "?key=nope"} {
+		if resp, _ := doRequest(t, "GET", u, nil, nil); resp.StatusCode != http.StatusForbidden {
+			t.Fatalf("read %s: got %s, want 403", u, resp.Status)
+		}
+	}
+
+	resp, _ = doRequest(t, "PUT", srv.URL+"/?encrypt&tar", bytes.NewReader(make([]byte, 1024)), nil)
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("encrypted tar upload: got %s, want 400", resp.Status)
+	}
+	resp, _ = doRequest(t, "PUT", srv.URL+"/?encrypt", strings.NewReader("tiny"), nil)
+	if resp.StatusCode != http.StatusNotAcceptable {
+		t.Fatalf("encrypted tiny paste: got %s, want 406", resp.Status)
+	}
+}
+
+func TestZeroKnowledgePaste(t *testing.T) {
+	srv := newTestServer(t)
+
+	sealed := `{"v":1,"iv":"AAECAwQFBgcICQoL","ct":"c2VhbGVkIHBhc3RlIGJ5dGVzIGFuZCB0YWc"}`
+	header := http.Header{"Content-Type": {"application/x-www-form-urlencoded"}}
+	form := url.Values{formVal: {sealed}}
+	resp, body := doRequest(t, "POST", srv.URL+"/?zk", strings.NewReader(form.Encode()), header)
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("post: %s %q", resp.Status, body)
+	}
+	pasteURL := strings.TrimSpace(body)
+
+	resp, body = doRequest(t, "GET", pasteURL, nil, nil)
+	if resp.StatusCode != http.StatusOK || body != sealed {
+		t.Fatalf("raw read: %s %q", resp.Status, body)
+	}
+	resp, body = doRequest(t, "GET", pasteURL, nil, http.Header{"Accept": {"text/html"}})
+	if resp.StatusCode != http.StatusOK || !strings.Contains(body, "crypto.subtle.decrypt") || !strings.Contains(body, "AAECAwQFBgcICQoL") {
+		t.Fatalf("decrypt page: %s %q", resp.Status, body)
+	}
+
+	form = url.Values{formVal: {testPaste}}
+	resp, _ = doRequest(t, "POST", srv.URL+"/?zk", strings.NewReader(form.Encode()), header)
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("plaintext zk post: got %s, want 400", resp.Status)
+	}
+	form = url.Values{formVal: {testPaste}, "zk": {"on"}}
+	resp, _ = doRequest(t, "POST", srv.URL+"/", strings.NewReader(form.Encode()), header)
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("post with the box ticked but no ?zk: got %s, want 400", resp.Status)
+	}
+	resp, _ = doRequest(t, "PUT", srv.URL+"/?zk", strings.NewReader(sealed), nil)
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("zk put: got %s, want 400", resp.Status)
+	}
+}
+
+func TestPasswordPaste(t *testing.T) {
+	srv := newTestServer(t)
+
+	auth := func(password string) http.Header {
+		return http.Header{"Authorization": {"Basic " + base64.StdEncoding.EncodeToString([]byte(":"+password))}}
+	}
+	resp, body := doRequest(t, "PUT", srv.URL+"/", strings.NewReader(testPaste), auth("hunter2"))
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("put: %s %q", resp.Status, body)
+	}
+	pasteURL := strings.TrimSpace(body)
+
+	resp, _ = doRequest(t, "PUT", srv.URL+"/?password=hunter2", strings.NewReader(testPaste), nil)
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("password in the url: got %s, want 400", resp.Status)
+	}
+	resp, body = doRequest(t, "PUT", srv.URL+"/", strings.NewReader(testPaste), http.Header{"X-Password": {"hunter2"}})
+	if resp, _ = doRequest(t, "GET", strings.TrimSpace(body), nil, nil); resp.StatusCode != http.StatusUnauthorized {
+		t.Fatalf("read of an X-Password paste: got %s, want 401", resp.Status)
+	}
+
+	resp, _ = doRequest(t, "GET", pasteURL, nil, nil)
+	if resp.StatusCode != http.StatusUnauthorized || resp.Header.Get("WWW-Authenticate") == "" {
+		t.Fatalf("read without a password: got %s, want 401 with a challenge", resp.Status)
+	}
+	resp, body = doRequest(t, "GET", pasteURL, nil, auth("hunter2"))
+	if resp.StatusCode != http.StatusOK || body != testPaste || resp.Header.Get("Cache-Control") != "private, no-store" {
+		t.Fatalf("read: %s %q cache %q", resp.Status, body, resp.Header.Get("Cache-Control"))
+	}
+
+	resp, body = doRequest(t, "GET", pasteURL, nil, http.Header{"Accept": {"text/html"}})
+	if resp.StatusCode != http.StatusUnauthorized || !strings.Contains(body, `type="password"`) {
+		t.Fatalf("prompt: %s %q", resp.Status, body)
+	}
+	form := url.Values{"password": {"hunter2"}}
+	header := http.Header{"Content-Type": {"application/x-www-form-urlencoded"}, "Accept": {"text/html"}}
+	resp, body = doRequest(t, "POST", pasteURL, strings.NewReader(form.Encode()), header)
+	if resp.StatusCode != http.StatusOK || body != testPaste {
+		t.Fatalf("read through the prompt: %s %q", resp.Status, body)
+	}
+	id := strings.TrimPrefix(pasteURL, srv.URL+"/")
+	if resp, _ = doRequest(t, "GET", srv.URL+"/diff/"+id+"/"+id, nil, auth("hunter2")); resp.StatusCode != http.StatusForbidden {
+		t.Fatalf("diff: got %s, want 403", resp.Status)
+	}
+
+	for i := 0; i < maxPasswordAttempts; i++ {
+		if resp, _ := doRequest(t, "GET", pasteURL, nil, auth("hunter3")); resp.StatusCode != http.StatusUnauthorized {
+			t.Fatalf("wrong password %d: got %s, want 401", i, resp.Status)
+		}
+	}
+	resp, _ = doRequest(t, "GET", pasteURL, nil, auth("hunter2"))
+	if resp.StatusCode != http.StatusTooManyRequests || resp.Header.Get("Retry-After") == "" {
+		t.Fatalf("after %d wrong passwords: got %s, want 429", maxPasswordAttempts, resp.Status)
+	}
+}
+
+func TestAttemptLimiter(t *testing.T) {
+	l := newAttemptLimiter(maxPasswordAttempts)
+	now := time.Now()
+	for i := 0; i < maxPasswordAttempts; i++ {
+		if wait := l.attempt("a", now); wait != 0 {
+			t.Fatalf("attempt %d: waited %s", i, wait)
+		}
+	}
+	if wait := l.attempt("a", now.Add(time.Minute)); wait != passwordLockout-time.Minute {
+		t.Fatalf("out of attempts: waited %s", wait)
+	}
+	if wait := l.attempt("b", now); wait != 0 {
+		t.Fatalf("other client: waited %s", wait)
+	}
+	if wait := l.attempt("a", now.Add(passwordLockout)); wait != 0 {
+		t.Fatalf("after the lockout: waited %s", wait)
+	}
+	l.forget("a")
+	if len(l.attempts) != 1 {
+		t.Fatalf("forget left %d clients, want 1", len(l.attempts))
+	}
+}
+
+func TestAgeRecipients(t *testing.T) {
+	srv := newTestServer(t)
+
+	var ids []*age.X25519Identity
+	query := url.Values{"json": {""}}
+	for i := 0; i < 2; i++ {
+		id, err := age.GenerateX25519Identity()
+		if err != nil {
+			t.Fatal(err)
+		}
+		ids = append(ids, id)
+		query.Add("to", id.Recipient().String())
+	}
+	resp, body := doRequest(t, "PUT", srv.URL+"/?"+query.Encode(), strings.NewReader(testPaste), nil)
+	var result uploadResult
+	if err := json.Unmarshal([]byte(body), &result); err != nil || resp.StatusCode != http.StatusOK {
+		t.Fatalf("put: %s %q %v", resp.Status, body, err)
+	}
+	if len(result.Recipients) != 2 || len(resp.Header.Values("X-Age-Recipient")) != 2 {
+		t.Fatalf("recipients: %q, headers %q", result.Recipients, resp.Header.Values("X-Age-Recipient"))
+	}
+
+	resp, body = doRequest(t, "GET", result.URL, nil, nil)
+	if resp.StatusCode != http.StatusOK || !strings.HasPrefix(body, armor.Header) || strings.Contains(body, "quick brown fox") {
+		t.Fatalf("read: %s %q", resp.Status, body)
+	}
+	for _, id := range ids {
+		r, err := age.Decrypt(armor.NewReader(strings.NewReader(body)), id)
+		if err != nil {
+			t.Fatal(err)
+		}
+		if plain, err := ioutil.ReadAll(r); err != nil || string(plain) != testPaste {
+			t.Fatalf("decrypted %q, %v", plain, err)
+		}
+	}
+
+	id := strings.TrimPrefix(result.URL, srv.URL+"/")
+	if resp, _ = doRequest(t, "GET", srv.URL+"/diff/"+id+"/"+id, nil, nil); resp.StatusCode != http.StatusForbidden {
+		t.Fatalf("diff: got %s, want 403", resp.Status)
+	}
+
+	resp, _ = doRequest(t, "PUT", srv.URL+"/?to=nope", strings.NewReader(testPaste), nil)
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("bad recipient: got %s, want 400", resp.Status)
+	}
+	resp, _ = doRequest(t, "PUT", srv.URL+"/?encrypt&"+query.Encode(), strings.NewReader(testPaste), nil)
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("?to with ?encrypt: got %s, want 400", resp.Status)
+	}
+}
+
+func TestContentEncoding(t *testing.T) {
+	s, idx := newMemStore(), newPasteIndex(t.TempDir())
+	srv := httptest.NewServer(newHandler(s, idx, newShortIDs(t.TempDir()), newNameIndex(t.TempDir()), true))
+	defer srv.Close()
+
+	// uploads can be gzipped, and text is stored zstd compressed
+	log := strings.Repeat(testPaste, 100)
+	var gz bytes.Buffer
+	zw := gzip.NewWriter(&gz)
+	zw.Write([]byte(log))
+	zw.Close()
+	resp, body := doRequest(t, "PUT", srv.URL+"/", &gz, http.Header{"Content-Encoding": {"gzip"}})
+	if resp.StatusCode != http.StatusOK {
+		t.Fatalf("put: %s %q", resp.Status, body)
+	}
+	pasteURL := strings.TrimSpace(body)
+	hash := pasteHash(resp)
+	if meta, _ := idx.get(hash); meta.Encoding != "zstd" {
+		t.Fatalf("stored with encoding %q, want zstd", meta.Encoding)
+	}
+	if info, err := s.Stat(hash); err != nil || info.Size >= int64(len(log)) {
+		t.Fatalf("stored %d bytes of %d, %v", info.Size, len(log), err)
+	}
+
+	// the transport would decompress by itself if it asked for gzip
+	tr := &http.Transport{DisableCompression: true}
+	defer tr.CloseIdleConnections()
+	for _, encoding := range []string{"", "gzip", "br", "zstd"} {
+		req, _ := http.NewRequest("GET", pasteURL, nil)
+		if encoding != "" {
+			req.Header.Set("Accept-Encoding", encoding)
+		}
+		resp, err := tr.RoundTrip(req)
+		if err != nil {
+			t.Fatal(err)
+		}
+		b, err := ioutil.ReadAll(resp.Body)
+		resp.Body.Close()
+		if err == nil && encoding != "" {
+			b, err = decodePaste(b, encoding)
+		}
+		if err != nil || string(b) != log || resp.Header.Get("Content-Encoding") != encoding {
+			t.Fatalf("accept %q: %s encoding %q, %d bytes, %v", encoding, resp.Status, resp.Header.Get("Content-Encoding"), len(b), err)
+		}
+	}
+
+	// named pastes are stored in a directory, which archives serve as is
+	resp, body = doRequest(t, "PUT", srv.URL+"/build.log", strings.NewReader(log), nil)
+	if meta, _ := idx.get(pasteHash(resp)); resp.StatusCode != http.StatusOK || meta.Encoding != "" {
+		t.Fatalf("named paste: %s %q, stored with encoding %q", resp.Status, body, meta.Encoding)
+	}
+	dir := pasteHash(resp)
+	resp, body = doRequest(t, "GET", srv.URL+"/"+dir+"?tar", nil, nil)
+	tr2 := tar.NewReader(strings.NewReader(body))
+	var got []byte
+	for {
+		hdr, err := tr2.Next()
+		if err == io.EOF {
+			break
+		} else if err != nil {
+			t.Fatalf("tar of a named paste: %s %v", resp.Status, err)
+		}
+		if hdr.Name == dir+"/build.log" {
+			got, _ = ioutil.ReadAll(tr2)
+		}
+	}
+	if string(got) != log {
+		t.Fatalf("tar of a named paste: got %d bytes of build.log, want %d", len(got), len(log))
+	}
+
+	// binary pastes and small ones are stored as they are
+	resp, _ = doRequest(t, "PUT", srv.URL+"/", bytes.NewReader(make([]byte, 4096)), nil)
+	if meta, _ := idx.get(pasteHash(resp)); resp.StatusCode != http.StatusOK || meta.Encoding != "" {
+		t.Fatalf("binary paste: %s, stored with encoding %q", resp.Status, meta.Encoding)
+	}
+	resp, _ = doRequest(t, "PUT", srv.URL+"/", strings.NewReader(testPaste), http.Header{"Content-Encoding": {"gzip"}})
+	if resp.StatusCode != http.StatusBadRequest {
+		t.Fatalf("bad gzip body: got %s, want 400", resp.Status)
+	}
+	resp, _ = doRequest(t, "PUT", srv.URL+"/", strings.NewReader(testPaste), http.Header{"Content-Encoding": {"compress"}})
+	if resp.StatusCode != http.StatusUnsupportedMediaType {
+		t.Fatalf("unknown encoding: got %s, want 415", resp.Status)
+	}
+}
+
+func TestAcceptsEncoding(t *testing.T) {
+	for _, tc := range []struct {
+		accept string
+		want   string
+	}{
+		{"", ""},
+		{"gzip, deflate", "gzip"},
+		{"gzip, br", "br"},
+		{"br;q=0, gzip;q=0.5", "gzip"},
+		{"zstd; q=1, identity", "zstd"},
+	} {
+		req := httptest.NewRequest("GET", "/", nil)
+		req.Header.Set("Accept-Encoding", tc.accept)
+		if got := negotiateEncoding(req); got != tc.want {
+			t.Errorf("Accept-Encoding %q: got %q, want %q", tc.accept, got, tc.want)
+		}
+	}
+}
+
+func TestEncryptChunks(t *testing.T) {
+	secret, err := newSecret()
+	if err != nil {
+		t.Fatal(err)
+	}
+	aead, err := secretAEAD(secret)
+	if err != nil {
+		t.Fatal(err)
+	}
+	seal := encryptSealer(aead)
+	for _, size := range []int{minPasteSize, sealChunkSize - 1, sealChunkSize, 2*sealChunkSize + 7} {
+		plain := bytes.Repeat([]byte{'x'}, size)
+		sealed, err := ioutil.ReadAll(seal.seal(bytes.NewReader(plain)))
+		if err != nil {
+			t.Fatalf("%d bytes: %v", size, err)
+		}
+		got, err := decryptPaste(sealed, aead)
+		if err != nil || !bytes.Equal(got, plain) {
+			t.Fatalf("%d bytes: round trip failed: %v", size, err)
+		}
+		if size > sealChunkSize {
+			// dropping the last chunk must not go unnoticed
+			if _, err := decryptPaste(sealed[:sealChunkSize+16], aead); err == nil {
+				t.Fatalf("%d bytes: truncated paste decrypted", size)
+			}
+		}
+	}
+}
+
+func TestReadNotFound(t *testing.T) {
+	srv := newTestServer(t)
+
+	for _, path := range []string{
+		"/QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o",
+		"/not-a-hash",
+	} {
+		resp, _ := doRequest(t, "GET", srv.URL+path, nil, nil)
+		if resp.StatusCode != http.StatusNotFound {
+			t.Errorf("%s: got %s, want 404", path, resp.Status)
+		}
+	}
+}